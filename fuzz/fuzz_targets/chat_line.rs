@@ -0,0 +1,33 @@
+//! Budget chat names and the lines of the room.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protohacker3::{validate_username, Event};
+
+fuzz_target!(|input: (&str, &str)| {
+    let (username, message) = input;
+
+    // Whatever the server sends can be read back
+    if let Some(event) = Event::from_line(message) {
+        assert_eq!(event.to_line(), format!("{message}\n"));
+    }
+
+    if !validate_username(username) {
+        return;
+    }
+    assert!(username.bytes().all(|b| b.is_ascii_alphanumeric()));
+
+    if message.contains('\n') {
+        return;
+    }
+    for event in [
+        Event::Joined(username.to_string()),
+        Event::Left(username.to_string()),
+        Event::Sent(username.to_string(), message.to_string()),
+    ] {
+        let line = event.to_line();
+        let line = line.strip_suffix('\n').expect("lines end with a newline");
+        assert_eq!(Event::from_line(line), Some(event));
+    }
+});
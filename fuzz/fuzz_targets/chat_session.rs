@@ -0,0 +1,89 @@
+//! Users joining, talking in and leaving a budget chat room, checking who
+//! gets which line.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use protohacker3::{parse_room_description, validate_username, BudgetChat, Event};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+#[derive(Arbitrary, Debug)]
+enum Action {
+    Join(String),
+    Send(u8, String),
+    Leave(u8),
+}
+
+/// Drain the lines queued for a user.
+fn received(outbox: &mut UnboundedReceiver<Arc<str>>) -> Vec<String> {
+    std::iter::from_fn(|| outbox.try_recv().ok())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fuzz_target!(|actions: Vec<Action>| {
+    let chat = BudgetChat::new();
+    let mut members: BTreeMap<String, UnboundedReceiver<Arc<str>>> = BTreeMap::new();
+
+    for action in actions {
+        // Who should hear about it, and what they should hear
+        let (author, line) = match action {
+            Action::Join(username) => {
+                if !validate_username(&username) {
+                    continue;
+                }
+
+                let (outbox, mut rx) = unbounded_channel();
+                let joined = chat.join(&username, &outbox);
+                assert_eq!(joined.is_ok(), !members.contains_key(&username));
+                if joined.is_err() {
+                    continue;
+                }
+
+                let description = received(&mut rx);
+                assert_eq!(description.len(), 1);
+                let line = description[0].strip_suffix('\n').unwrap();
+                let mut listed = parse_room_description(line).unwrap();
+                listed.sort();
+                assert!(listed.iter().eq(members.keys()));
+
+                // The receiver must outlive the room's sender
+                members.insert(username.clone(), rx);
+                std::mem::forget(outbox);
+                (username.clone(), Event::Joined(username).to_line())
+            }
+            Action::Send(user, message) => {
+                let Some(username) = members.keys().nth(usize::from(user)).cloned() else {
+                    continue;
+                };
+                if message.contains('\n') {
+                    continue;
+                }
+                let event = Event::Sent(username.clone(), message);
+                let line = event.to_line();
+                chat.broadcast(event);
+                (username, line)
+            }
+            Action::Leave(user) => {
+                let Some(username) = members.keys().nth(usize::from(user)).cloned() else {
+                    continue;
+                };
+                members.remove(&username);
+                chat.leave(&username, true);
+                (username.clone(), Event::Left(username).to_line())
+            }
+        };
+
+        for (username, outbox) in &mut members {
+            let expected = if *username == author {
+                vec![]
+            } else {
+                vec![line.clone()]
+            };
+            assert_eq!(received(outbox), expected, "lines of {username}");
+        }
+    }
+});
@@ -0,0 +1,41 @@
+//! Datagrams sent to the unusual database program, checked against a plain
+//! map.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protohacker4::{Database, BUFF_SIZE, VERSION_KEY};
+use std::collections::HashMap;
+
+fuzz_target!(|datagrams: Vec<Vec<u8>>| {
+    let mut database = Database::new();
+    let mut model: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+    model.insert(VERSION_KEY.into(), b"1.0".to_vec());
+
+    for datagram in datagrams {
+        let reply = database.handle_datagram(&datagram);
+        let is_text = std::str::from_utf8(&datagram).is_ok();
+
+        match datagram.iter().position(|&b| b == b'=') {
+            Some(eq) => {
+                assert!(matches!(reply, Ok(None)) || !is_text);
+
+                let (key, value) = (&datagram[..eq], &datagram[eq + 1..]);
+                // Keys and values are checked separately, so the datagram is
+                // only stored if both are text
+                let stored = std::str::from_utf8(key).is_ok() && std::str::from_utf8(value).is_ok();
+                if stored && key != VERSION_KEY.as_bytes() {
+                    model.insert(key.to_vec(), value.to_vec());
+                }
+            }
+            None if !is_text => assert!(reply.is_err()),
+            None => {
+                let value = model.get(&datagram).cloned().unwrap_or_default();
+                let mut expected = [&datagram[..], b"=", &value].concat();
+                expected.truncate(BUFF_SIZE);
+
+                assert_eq!(reply.unwrap(), Some(expected));
+            }
+        }
+    }
+});
@@ -0,0 +1,47 @@
+//! Length prefixed framing: messages encoded then read back, in arbitrary
+//! chunks, come out as they went in.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocore::codec::{Codec, Framed, LengthPrefixedCodec};
+use protocore::io::ReadError;
+use protohacker_fuzz::{block_on, Chunked};
+
+fuzz_target!(|input: (u16, Vec<Vec<u8>>, Vec<u16>)| {
+    let (max, messages, cuts) = input;
+    let max = usize::from(max);
+    let codec = LengthPrefixedCodec::new(max);
+
+    let mut data = Vec::new();
+    for message in &messages {
+        codec.encode(message, &mut data);
+    }
+
+    // Split the encoded stream where the fuzzer says
+    let mut chunks = Vec::new();
+    let mut rest = &data[..];
+    for cut in cuts {
+        let (chunk, tail) = rest.split_at(usize::from(cut).min(rest.len()));
+        chunks.push(chunk.to_vec());
+        rest = tail;
+    }
+    chunks.push(rest.to_vec());
+
+    let mut reader = Framed::new(Chunked::new(chunks), codec);
+
+    block_on(async {
+        for expected in &messages {
+            match reader.read_message().await {
+                Ok(message) => assert_eq!(&message, expected),
+                Err(ReadError::TooLong) => {
+                    assert!(expected.len() > max);
+                    return;
+                }
+                Err(e) => panic!("unexpected error {e}"),
+            }
+        }
+
+        assert!(matches!(reader.read_message().await, Err(ReadError::Eof)));
+    });
+});
@@ -0,0 +1,39 @@
+//! The newline delimited reader every text protocol is built on, checked
+//! against splitting the whole input at once.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocore::io::{LineReader, ReadError};
+use protohacker_fuzz::{block_on, Chunked};
+
+fuzz_target!(|input: (u16, Vec<Vec<u8>>)| {
+    let (max, chunks) = input;
+    let max = usize::from(max).max(1);
+
+    let data = chunks.concat();
+    let mut reader = LineReader::new(Chunked::new(chunks), max);
+
+    block_on(async {
+        let mut rest = &data[..];
+
+        loop {
+            let expected = match rest.iter().position(|&b| b == b'\n') {
+                Some(end) if end < max => Ok(&rest[..end]),
+                Some(_) => Err(ReadError::TooLong),
+                None if rest.len() >= max => Err(ReadError::TooLong),
+                None => Err(ReadError::Eof),
+            };
+
+            match (reader.read_line().await, expected) {
+                (Ok(line), Ok(expected)) => {
+                    assert_eq!(line, expected);
+                    rest = &rest[line.len() + 1..];
+                }
+                (Err(ReadError::TooLong), Err(ReadError::TooLong))
+                | (Err(ReadError::Eof), Err(ReadError::Eof)) => return,
+                (actual, expected) => panic!("read {actual:?}, expected {expected:?}"),
+            }
+        }
+    });
+});
@@ -0,0 +1,12 @@
+//! A single means to an end message.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protohacker2::{Command, COMMAND_SIZE};
+
+fuzz_target!(|frame: [u8; COMMAND_SIZE]| {
+    if let Ok(command) = Command::parse(&frame) {
+        assert_eq!(command.to_bytes(), frame);
+    }
+});
@@ -0,0 +1,52 @@
+//! A whole means to an end session, checked against a naive model of the
+//! prices.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use protohacker2::{Command, Session};
+
+#[derive(Arbitrary, Debug)]
+enum Message {
+    Insert(i32, i32),
+    Query(i32, i32),
+}
+
+fuzz_target!(|messages: Vec<Message>| {
+    let mut session = Session::new();
+    let mut prices: Vec<(i32, i32)> = Vec::new();
+
+    for message in messages {
+        match message {
+            Message::Insert(timestamp, price) => {
+                let reply = session.handle(&Command::insert(timestamp, price).to_bytes());
+                assert!(reply.expect("inserts are valid").is_none());
+
+                // Behaviour is undefined for a timestamp inserted twice, we
+                // keep the first price
+                if prices.iter().all(|&(t, _)| t != timestamp) {
+                    prices.push((timestamp, price));
+                }
+            }
+            Message::Query(min_time, max_time) => {
+                let reply = session.handle(&Command::query(min_time, max_time).to_bytes());
+                let mean = reply
+                    .expect("queries are valid")
+                    .expect("queries get a reply");
+
+                let in_range: Vec<i64> = prices
+                    .iter()
+                    .filter(|&&(t, _)| min_time <= t && t <= max_time)
+                    .map(|&(_, price)| price.into())
+                    .collect();
+                let expected = match in_range.len() {
+                    0 => 0,
+                    n => in_range.iter().sum::<i64>() / n as i64,
+                };
+
+                assert_eq!(i64::from(mean.value), expected);
+            }
+        }
+    }
+});
@@ -0,0 +1,34 @@
+//! Boguscoin rewriting of a single chat line: addresses, and only them, are
+//! replaced with Tony's.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protohacker5::{rewrite_message, BOGUS_REGEX};
+use protohacker_fuzz::is_boguscoin;
+use regex::Regex;
+use std::sync::LazyLock;
+
+const TONY: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
+
+static REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(BOGUS_REGEX).unwrap());
+
+fuzz_target!(|message: &str| {
+    if message.contains('\n') {
+        return;
+    }
+
+    let rewritten = rewrite_message(&REGEX, message);
+    let rewritten = rewritten
+        .strip_suffix('\n')
+        .expect("the newline is added back");
+
+    let words: Vec<&str> = message.split(' ').collect();
+    let rewritten: Vec<&str> = rewritten.split(' ').collect();
+    assert_eq!(words.len(), rewritten.len(), "{message:?}");
+
+    for (word, rewritten) in words.iter().zip(rewritten) {
+        let expected = if is_boguscoin(word) { TONY } else { word };
+        assert_eq!(rewritten, expected, "in {message:?}");
+    }
+});
@@ -0,0 +1,46 @@
+//! One direction of a proxied session: every line comes out rewritten, in
+//! order, until the source closes or sends a line too long.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocore::limits::Timeouts;
+use protohacker5::{proxy_and_rewrite, rewrite_message, Settings, MAX_LINE_SIZE};
+use protohacker_fuzz::{block_on, Chunked};
+use std::sync::LazyLock;
+
+static SETTINGS: LazyLock<Settings> = LazyLock::new(Settings::default);
+
+fuzz_target!(|chunks: Vec<Vec<u8>>| {
+    let input = chunks.concat();
+    let mut output = Vec::new();
+
+    let result = block_on(proxy_and_rewrite(
+        "[fuzz]",
+        Chunked::new(chunks),
+        &mut output,
+        &SETTINGS,
+        Timeouts::default(),
+    ));
+
+    // Whole lines come out rewritten, until one is too long or not text
+    let mut expected = String::new();
+    let mut rest = &input[..];
+    let clean = loop {
+        let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+            break rest.len() < MAX_LINE_SIZE;
+        };
+        if end >= MAX_LINE_SIZE {
+            break false;
+        }
+        let Ok(line) = std::str::from_utf8(&rest[..end]) else {
+            break false;
+        };
+
+        expected.push_str(&rewrite_message(&SETTINGS.pattern, line));
+        rest = &rest[end + 1..];
+    };
+
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+    assert_eq!(result.is_ok(), clean);
+});
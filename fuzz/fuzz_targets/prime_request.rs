@@ -0,0 +1,16 @@
+//! A single prime time request line, newline excluded.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protohacker1::{check_request, generate_response, ServerReply};
+
+fuzz_target!(|request: &[u8]| {
+    let is_prime = check_request(request);
+    let response = generate_response(&is_prime);
+
+    assert_eq!(response.last(), Some(&b'}'));
+
+    let reply: ServerReply = serde_json::from_slice(&response).expect("the reply is not json");
+    assert_eq!(reply.is_malformed(), is_prime.is_err());
+});
@@ -0,0 +1,39 @@
+//! A whole prime time session: the bytes a client sends, in the chunks they
+//! arrive in. Requests are answered in order until a malformed one.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocore::codec::Framed;
+use protocore::io::ReadError;
+use protocore::protocol::{Flow, Protocol, Replies};
+use protohacker1::PrimeTime;
+use protohacker_fuzz::{block_on, Chunked};
+
+fuzz_target!(|chunks: Vec<Vec<u8>>| {
+    let input = chunks.concat();
+    let prime = PrimeTime::default();
+    let mut reader = Framed::new(Chunked::new(chunks), prime.codec());
+    let mut consumed = 0;
+
+    block_on(async {
+        loop {
+            let request = match reader.read_message().await {
+                Ok(request) => request,
+                Err(ReadError::Eof | ReadError::TooLong) => return,
+                Err(e) => panic!("unexpected error {e}"),
+            };
+
+            // Requests come out whole and in order
+            assert_eq!(&input[consumed..consumed + request.len()], request);
+            consumed += request.len() + 1;
+            assert_eq!(input[consumed - 1], b'\n');
+
+            let mut replies = Replies::default();
+            match prime.on_message(&mut (), request, &mut replies) {
+                Ok(flow) => assert_eq!(flow, Flow::Continue),
+                Err(_) => return,
+            }
+        }
+    });
+});
@@ -0,0 +1,66 @@
+//! Helpers shared by the fuzz targets.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Run a future to completion on a single threaded runtime. No I/O happens
+/// outside of memory, so it never actually waits.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("failed to build a runtime")
+        .block_on(future)
+}
+
+/// A stream handing out its bytes in the chunks it was given, the way a
+/// client's writes may arrive split or coalesced.
+pub struct Chunked {
+    chunks: Vec<Vec<u8>>,
+    next: usize,
+    offset: usize,
+}
+
+impl Chunked {
+    pub fn new(chunks: Vec<Vec<u8>>) -> Chunked {
+        Chunked {
+            chunks,
+            next: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl AsyncRead for Chunked {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        // Skip empty chunks, a read of 0 bytes would mean the end of the stream
+        while this.next < this.chunks.len() && this.offset == this.chunks[this.next].len() {
+            this.next += 1;
+            this.offset = 0;
+        }
+
+        if let Some(chunk) = this.chunks.get(this.next) {
+            let n = buf.remaining().min(chunk.len() - this.offset);
+            buf.put_slice(&chunk[this.offset..this.offset + n]);
+            this.offset += n;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Whether `word` is a boguscoin address: a 7 followed by 25 to 34 ASCII
+/// letters or digits.
+pub fn is_boguscoin(word: &str) -> bool {
+    word.starts_with('7')
+        && (26..=35).contains(&word.len())
+        && word.bytes().all(|b| b.is_ascii_alphanumeric())
+}
@@ -0,0 +1,68 @@
+use crate::ClientError;
+use protocore::io::{send_to_socket, LineReader, ReadError};
+use protohacker3::{parse_room_description, Event, MAX_LINE_SIZE, SHUTDOWN_MESSAGE};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// Client of budget chat (protohacker3), joined to the room.
+pub struct ChatClient {
+    reader: LineReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    members: Vec<String>,
+}
+
+impl ChatClient {
+    /// Connect and join the room as `username`.
+    pub async fn join(addr: impl ToSocketAddrs, username: &str) -> Result<ChatClient, ClientError> {
+        let (read, mut writer) = TcpStream::connect(addr).await?.into_split();
+        let mut reader = LineReader::new(read, MAX_LINE_SIZE);
+
+        // Servers pick their own welcome, any line asks for the name
+        reader.read_line_utf8().await?;
+
+        send_to_socket(&mut writer, format!("{username}\n").as_bytes()).await?;
+
+        // The server hangs up on invalid or taken names
+        let description = match reader.read_line_utf8().await {
+            Err(ReadError::Eof) => return Err(ClientError::Rejected),
+            description => description?,
+        };
+        let members =
+            parse_room_description(&description).ok_or(ClientError::InvalidReply(description))?;
+
+        Ok(ChatClient {
+            reader,
+            writer,
+            members,
+        })
+    }
+
+    /// Who was in the room when we joined.
+    pub fn members(&self) -> &[String] {
+        &self.members
+    }
+
+    /// Send a message to the rest of the room.
+    pub async fn send(&mut self, message: &str) -> Result<(), ClientError> {
+        send_to_socket(&mut self.writer, format!("{message}\n").as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Wait for the next event of the room. `None` once the server hangs up.
+    ///
+    /// This is cancel safe, so it can be raced against other input.
+    pub async fn next_event(&mut self) -> Result<Option<Event>, ClientError> {
+        let line = match self.reader.read_line_utf8().await {
+            Err(ReadError::Eof) => return Ok(None),
+            line => line?,
+        };
+
+        if line == SHUTDOWN_MESSAGE.trim_end() {
+            return Ok(None);
+        }
+
+        Event::from_line(&line)
+            .map(Some)
+            .ok_or(ClientError::InvalidReply(line))
+    }
+}
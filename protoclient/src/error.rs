@@ -0,0 +1,54 @@
+use protocore::io::ReadError;
+use std::fmt;
+
+/// Why a request to a server failed.
+#[derive(Debug)]
+pub enum ClientError {
+    Io(std::io::Error),
+    Read(ReadError),
+    /// The server sent something its protocol does not allow.
+    InvalidReply(String),
+    /// The server turned the request down, e.g. a chat name already taken.
+    Rejected,
+    /// Keys of the unusual database program cannot contain `=`.
+    InvalidKey(String),
+    /// No reply came back, even after retrying.
+    TimedOut,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "{e}"),
+            ClientError::Read(e) => write!(f, "failed to read the reply: {e}"),
+            ClientError::InvalidReply(reply) => write!(f, "unexpected reply `{reply}`"),
+            ClientError::Rejected => write!(f, "the server rejected the request"),
+            ClientError::InvalidKey(key) => {
+                write!(f, "invalid key `{key}`: keys cannot contain `=`")
+            }
+            ClientError::TimedOut => write!(f, "the server did not reply"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::Read(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<ReadError> for ClientError {
+    fn from(e: ReadError) -> Self {
+        ClientError::Read(e)
+    }
+}
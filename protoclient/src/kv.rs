@@ -0,0 +1,87 @@
+use crate::ClientError;
+use protohacker4::VERSION_KEY;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
+
+/// How long to wait for a reply before asking again.
+const RETRY_AFTER: Duration = Duration::from_millis(500);
+
+/// How many times to ask before giving up.
+const ATTEMPTS: usize = 5;
+
+/// Largest payload of a UDP datagram, so no reply is cut short whatever
+/// `max_datagram_size` the server runs with.
+const MAX_REPLY_SIZE: usize = 65507;
+
+/// Client of the unusual database program (protohacker4).
+///
+/// Requests are single datagrams, so queries are retried until a reply comes
+/// back. Inserts get no reply and a lost one goes unnoticed.
+pub struct KvClient {
+    socket: UdpSocket,
+}
+
+fn check_key(key: &str) -> Result<(), ClientError> {
+    if key.contains('=') {
+        return Err(ClientError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+impl KvClient {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<KvClient, ClientError> {
+        let addr = lookup_host(addr).await?.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "no address to connect to")
+        })?;
+
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+
+        Ok(KvClient { socket })
+    }
+
+    /// Set `key` to `value`.
+    pub async fn set(&self, key: &str, value: &str) -> Result<(), ClientError> {
+        check_key(key)?;
+        self.socket
+            .send(format!("{key}={value}").as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// The value of `key`, empty if it was never set.
+    pub async fn get(&self, key: &str) -> Result<String, ClientError> {
+        check_key(key)?;
+
+        let prefix = format!("{key}=");
+        let mut reply = vec![0; MAX_REPLY_SIZE];
+
+        for _ in 0..ATTEMPTS {
+            self.socket.send(key.as_bytes()).await?;
+
+            // Replies to earlier attempts, or to other keys, may still be in flight
+            while let Ok(received) =
+                tokio::time::timeout(RETRY_AFTER, self.socket.recv(&mut reply)).await
+            {
+                let reply = &reply[..received?];
+                if let Some(value) = reply.strip_prefix(prefix.as_bytes()) {
+                    return String::from_utf8(value.to_vec()).map_err(|_| {
+                        ClientError::InvalidReply(String::from_utf8_lossy(reply).into())
+                    });
+                }
+            }
+        }
+
+        Err(ClientError::TimedOut)
+    }
+
+    /// The version of the server.
+    pub async fn version(&self) -> Result<String, ClientError> {
+        self.get(VERSION_KEY).await
+    }
+}
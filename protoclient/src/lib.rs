@@ -0,0 +1,14 @@
+//! Typed clients for the protohackers servers, speaking the same messages the
+//! servers parse.
+
+mod chat;
+mod error;
+mod kv;
+mod means;
+mod prime;
+
+pub use chat::ChatClient;
+pub use error::ClientError;
+pub use kv::KvClient;
+pub use means::MeansClient;
+pub use prime::PrimeClient;
@@ -0,0 +1,142 @@
+use clap::{Parser, Subcommand};
+use protoclient::{ChatClient, ClientError, KvClient, MeansClient, PrimeClient};
+use protohacker2::Command as MeansCommand;
+use std::process::ExitCode;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// Talk to the protohackers servers without hand-crafting their messages.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Server to talk to
+    #[arg(short, long, env = "PROTOHACKER_TARGET", value_name = "HOST:PORT")]
+    target: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prime time: ask whether each number is prime
+    Prime {
+        #[arg(required = true, allow_negative_numbers = true)]
+        numbers: Vec<f64>,
+    },
+    /// Means to an end: send inserts and queries in order, e.g. `I:12345:101 Q:12288:16384`
+    Means {
+        #[arg(
+            required = true,
+            allow_hyphen_values = true,
+            value_name = "I:TIMESTAMP:PRICE|Q:MIN:MAX",
+            value_parser = parse_means_command
+        )]
+        commands: Vec<MeansCommand>,
+    },
+    /// Budget chat: join as NAME, sending the lines of stdin and printing the room
+    Chat { name: String },
+    /// Unusual database program
+    Kv {
+        #[command(subcommand)]
+        command: KvCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum KvCommand {
+    /// Print the value of a key
+    Get { key: String },
+    /// Set a key
+    Set { key: String, value: String },
+    /// Print the version of the server
+    Version,
+}
+
+fn parse_means_command(s: &str) -> Result<MeansCommand, String> {
+    let mut parts = s.splitn(3, ':');
+    let (Some(kind), Some(first), Some(second)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!(
+            "expected I:TIMESTAMP:PRICE or Q:MIN:MAX, got `{s}`"
+        ));
+    };
+
+    let number = |n: &str| {
+        n.parse::<i32>()
+            .map_err(|e| format!("invalid number `{n}`: {e}"))
+    };
+    let (first, second) = (number(first)?, number(second)?);
+
+    match kind {
+        "I" | "i" => Ok(MeansCommand::insert(first, second)),
+        "Q" | "q" => Ok(MeansCommand::query(first, second)),
+        _ => Err(format!("unknown command `{kind}`, expected I or Q")),
+    }
+}
+
+async fn chat(target: &str, name: &str) -> Result<(), ClientError> {
+    let mut client = ChatClient::join(target, name).await?;
+    println!("* in the room: {}", client.members().join(", "));
+
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        tokio::select! {
+            line = stdin.next_line() => match line? {
+                Some(line) => client.send(&line).await?,
+                None => return Ok(()),
+            },
+            event = client.next_event() => match event? {
+                Some(event) => print!("{}", event.to_line()),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), ClientError> {
+    let target = cli.target.as_str();
+
+    match cli.command {
+        Command::Prime { numbers } => {
+            let mut client = PrimeClient::connect(target).await?;
+            for number in numbers {
+                let answer = if client.is_prime(number).await? {
+                    "prime"
+                } else {
+                    "not prime"
+                };
+                println!("{number}: {answer}");
+            }
+        }
+        Command::Means { commands } => {
+            let mut client = MeansClient::connect(target).await?;
+            for command in commands {
+                if let Some(mean) = client.send(command).await? {
+                    println!("{mean}");
+                }
+            }
+        }
+        Command::Chat { name } => chat(target, &name).await?,
+        Command::Kv { command } => {
+            let client = KvClient::connect(target).await?;
+            match command {
+                KvCommand::Get { key } => println!("{}", client.get(&key).await?),
+                KvCommand::Set { key, value } => client.set(&key, &value).await?,
+                KvCommand::Version => println!("{}", client.version().await?),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
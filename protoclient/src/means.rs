@@ -0,0 +1,46 @@
+use crate::ClientError;
+use protocore::io::{read_frame, send_to_socket};
+use protocore::limits::Timeouts;
+use protohacker2::{Command, CommandType, Response};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// Client of means to an end (protohacker2). Prices are kept per connection,
+/// so they are gone once the client is dropped.
+pub struct MeansClient {
+    stream: TcpStream,
+}
+
+impl MeansClient {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<MeansClient, ClientError> {
+        Ok(MeansClient {
+            stream: TcpStream::connect(addr).await?,
+        })
+    }
+
+    /// Send any command, returning the reply to queries.
+    pub async fn send(&mut self, command: Command) -> Result<Option<i32>, ClientError> {
+        send_to_socket(&mut self.stream, &command.to_bytes()).await?;
+
+        match command.c_type {
+            CommandType::Insert => Ok(None),
+            CommandType::Query => {
+                let reply =
+                    read_frame::<{ Response::SIZE }, _>(&mut self.stream, &Timeouts::default())
+                        .await?;
+                Ok(Some(Response::parse(&reply).value))
+            }
+        }
+    }
+
+    /// Record the price of the asset at `timestamp`.
+    pub async fn insert(&mut self, timestamp: i32, price: i32) -> Result<(), ClientError> {
+        self.send(Command::insert(timestamp, price)).await?;
+        Ok(())
+    }
+
+    /// The mean price between `min_time` and `max_time`, both included.
+    pub async fn query(&mut self, min_time: i32, max_time: i32) -> Result<i32, ClientError> {
+        let mean = self.send(Command::query(min_time, max_time)).await?;
+        Ok(mean.expect("queries always get a reply"))
+    }
+}
@@ -0,0 +1,37 @@
+use crate::ClientError;
+use protocore::io::{send_to_socket, LineReader};
+use protohacker1::{ServerReply, ServerRequest, MAX_REQUEST_SIZE};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// Client of prime time (protohacker1).
+pub struct PrimeClient {
+    reader: LineReader<TcpStream>,
+}
+
+impl PrimeClient {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<PrimeClient, ClientError> {
+        let stream = TcpStream::connect(addr).await?;
+
+        Ok(PrimeClient {
+            reader: LineReader::new(stream, MAX_REQUEST_SIZE),
+        })
+    }
+
+    /// Ask the server whether `number` is prime.
+    pub async fn is_prime(&mut self, number: f64) -> Result<bool, ClientError> {
+        let mut request = serde_json::to_vec(&ServerRequest::is_prime(number))
+            .expect("requests always serialize");
+        request.push(b'\n');
+        send_to_socket(self.reader.get_mut(), &request).await?;
+
+        let line = self.reader.read_line().await?;
+        let reply: ServerReply = serde_json::from_slice(&line)
+            .map_err(|_| ClientError::InvalidReply(String::from_utf8_lossy(&line).into()))?;
+
+        if reply.is_malformed() {
+            return Err(ClientError::Rejected);
+        }
+
+        Ok(reply.prime)
+    }
+}
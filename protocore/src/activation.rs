@@ -0,0 +1,123 @@
+//! Systemd socket activation: listeners bound by systemd and handed over
+//! through `LISTEN_FDS`, e.g. to serve port 80 without ever running as root.
+//!
+//! The inherited sockets are claimed by the servers whose listen addresses
+//! match them, in place of binding their own, so the same flags or config
+//! work with and without activation. A `ListenStream=80` socket unit is
+//! picked up by a server listening on port 80, on any address unless it was
+//! given one.
+
+use socket2::{Socket, Type};
+use std::net::SocketAddr;
+use std::os::fd::{FromRawFd, RawFd};
+use tracing::{info, warn};
+
+/// First file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// An inherited socket not claimed yet.
+struct Inherited {
+    addr: SocketAddr,
+    socket: Socket,
+}
+
+/// The sockets systemd passed to this process and no server claimed yet.
+#[derive(Default)]
+pub struct Activation {
+    inherited: Vec<Inherited>,
+}
+
+impl Activation {
+    /// Read `LISTEN_PID` and `LISTEN_FDS`, taking ownership of the sockets
+    /// when they are meant for this process.
+    ///
+    /// The variables are removed from the environment, so call this at the
+    /// top of `main`, before a runtime or anything else starts a thread.
+    pub fn from_env() -> Activation {
+        let pid = std::env::var("LISTEN_PID").ok();
+        let fds = std::env::var("LISTEN_FDS").ok();
+        let (Some(pid), Some(fds)) = (pid, fds) else {
+            return Activation::default();
+        };
+
+        // They are for us only; a process we spawn must not pick them up
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        if pid.parse() != Ok(std::process::id()) {
+            return Activation::default();
+        }
+        let Ok(fds) = fds.parse::<RawFd>() else {
+            warn!(fds, "ignoring LISTEN_FDS, not a number");
+            return Activation::default();
+        };
+
+        let mut inherited = Vec::new();
+        for fd in LISTEN_FDS_START..LISTEN_FDS_START + fds {
+            // SAFETY: systemd hands these descriptors over to us, and nothing
+            // else in the process uses them
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            if let Err(e) = socket.set_cloexec(true) {
+                warn!(fd, error = %e, "ignoring an inherited socket");
+                continue;
+            }
+            match socket.local_addr().map(|addr| addr.as_socket()) {
+                Ok(Some(addr)) => inherited.push(Inherited { addr, socket }),
+                _ => warn!(fd, "ignoring an inherited socket, not an IP one"),
+            }
+        }
+
+        Activation { inherited }
+    }
+
+    /// Claim the inherited sockets of type `ty` matching `addr`.
+    fn take(&mut self, addr: SocketAddr, ty: Type) -> Vec<Socket> {
+        let mut taken = Vec::new();
+
+        let mut i = 0;
+        while i < self.inherited.len() {
+            let socket = &self.inherited[i];
+            if matches(socket.addr, addr) && socket.socket.r#type().is_ok_and(|t| t == ty) {
+                let socket = self.inherited.swap_remove(i);
+                info!(addr = %socket.addr, "using a listener passed by systemd");
+                taken.push(socket.socket);
+            } else {
+                i += 1;
+            }
+        }
+
+        taken
+    }
+
+    /// The inherited TCP listeners standing for `addr`, if any.
+    pub fn take_tcp(&mut self, addr: SocketAddr) -> Vec<std::net::TcpListener> {
+        self.take(addr, Type::STREAM)
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// The inherited UDP sockets standing for `addr`, if any.
+    pub fn take_udp(&mut self, addr: SocketAddr) -> Vec<std::net::UdpSocket> {
+        self.take(addr, Type::DGRAM)
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Warn about the inherited sockets no server claimed. Call once every
+    /// server is bound.
+    pub fn warn_unclaimed(&self) {
+        for socket in &self.inherited {
+            warn!(addr = %socket.addr, "no service listens on a socket passed by systemd");
+        }
+    }
+}
+
+/// Whether an inherited socket bound to `inherited` stands for a listener
+/// bound to `wanted`: the same port, and the same address unless any will do.
+fn matches(inherited: SocketAddr, wanted: SocketAddr) -> bool {
+    inherited.port() == wanted.port()
+        && (wanted.ip().is_unspecified() || inherited.ip() == wanted.ip())
+}
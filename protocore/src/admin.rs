@@ -0,0 +1,234 @@
+//! A local admin interface on a Unix socket: list the open connections, kick
+//! a client, dump the state of a service and change what gets logged.
+//!
+//! Each connection to the socket sends a single command line and gets the
+//! answer back before the socket is closed, e.g. with
+//! `echo connections | socat - UNIX-CONNECT:/run/protohacker.sock`:
+//!
+//! - `connections [SERVICE]`: one line per open connection, `ID SERVICE PEER
+//!   AGE`
+//! - `kick ID`: disconnect a client
+//! - `dump SERVICE`: the state of a service that exposes one, e.g. the keys of
+//!   kv or the users in chat
+//! - `log [FILTER]`: the log filter, replaced by `FILTER` if given, in
+//!   `PROTOHACKER_LOG` syntax
+//!
+//! Like the metrics, the connections and dumps are kept in a process-wide
+//! registry, filled by the accept loops.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::net::{remove_stale_socket, Peer};
+use crate::shutdown::Shutdown;
+
+/// Largest command line the socket reads.
+const MAX_COMMAND: u64 = 4096;
+
+/// Gives the state of a service as text.
+type Dump = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// An open connection, as listed by the admin socket.
+struct Connection {
+    service: &'static str,
+    peer: Peer,
+    since: Instant,
+    kick: CancellationToken,
+}
+
+/// The open connections and the dumps of every service.
+#[derive(Default)]
+pub struct Registry {
+    connections: Mutex<BTreeMap<u64, Connection>>,
+    dumps: Mutex<BTreeMap<&'static str, Dump>>,
+}
+
+/// Keeps a connection listed until dropped.
+pub struct Registered {
+    id: u64,
+    kick: CancellationToken,
+}
+
+impl Registered {
+    /// Resolves once the connection is kicked.
+    pub async fn kicked(&self) {
+        self.kick.cancelled().await
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        registry().connections.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Render a duration the way a human reads it, to the second.
+fn age(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{}s", secs / 60, secs % 60),
+        _ => format!("{}h{}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+impl Registry {
+    /// List a connection until the returned guard is dropped.
+    pub fn open(&self, service: &'static str, id: u64, peer: Peer) -> Registered {
+        let kick = CancellationToken::new();
+        let connection = Connection {
+            service,
+            peer,
+            since: Instant::now(),
+            kick: kick.clone(),
+        };
+        self.connections.lock().unwrap().insert(id, connection);
+
+        Registered { id, kick }
+    }
+
+    /// Disconnect the connection `id`. Returns whether it was open.
+    pub fn kick(&self, id: u64) -> bool {
+        match self.connections.lock().unwrap().get(&id) {
+            Some(connection) => {
+                connection.kick.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The open connections, of `service` only if given.
+    pub fn connections(&self, service: Option<&str>) -> String {
+        let connections = self.connections.lock().unwrap();
+        let mut out = String::new();
+
+        for (id, connection) in connections.iter() {
+            if service.is_some_and(|service| service != connection.service) {
+                continue;
+            }
+            let _ = writeln!(
+                out,
+                "{id} {} {} {}",
+                connection.service,
+                connection.peer,
+                age(connection.since.elapsed())
+            );
+        }
+
+        out
+    }
+
+    /// Let `dump SERVICE` give the state of the service, replacing the dump
+    /// it had.
+    pub fn set_dump(
+        &self,
+        service: &'static str,
+        dump: impl Fn() -> String + Send + Sync + 'static,
+    ) {
+        self.dumps.lock().unwrap().insert(service, Arc::new(dump));
+    }
+
+    /// The state of `service`, if it has a dump.
+    pub fn dump(&self, service: &str) -> Option<String> {
+        let dump = self.dumps.lock().unwrap().get(service).cloned();
+        dump.map(|dump| dump())
+    }
+}
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+/// The registry of the process, served on the admin socket.
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// The answer to a command line.
+fn run(command: &str) -> String {
+    let mut words = command.split_whitespace();
+
+    match (words.next(), words.next(), words.next()) {
+        (Some("connections"), service, None) => registry().connections(service),
+        (Some("kick"), Some(id), None) => match id.parse() {
+            Ok(id) if registry().kick(id) => format!("kicked {id}\n"),
+            Ok(id) => format!("error: no connection {id}\n"),
+            Err(_) => format!("error: `{id}` is not a connection id\n"),
+        },
+        (Some("dump"), Some(service), None) => registry()
+            .dump(service)
+            .unwrap_or_else(|| format!("error: {service} has nothing to dump\n")),
+        (Some("log"), None, None) => match crate::logging::filter() {
+            Some(filter) => format!("{filter}\n"),
+            None => "error: logging is not set up\n".to_string(),
+        },
+        (Some("log"), Some(filter), None) => match crate::logging::set_filter(filter) {
+            Ok(()) => {
+                info!(filter, "changed the log filter");
+                format!("{filter}\n")
+            }
+            Err(e) => format!("error: {e}\n"),
+        },
+        _ => {
+            "error: expected `connections [SERVICE]`, `kick ID`, `dump SERVICE` or `log [FILTER]`\n"
+                .to_string()
+        }
+    }
+}
+
+/// Answer a single command, then close the connection.
+async fn answer(stream: UnixStream) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut command = String::new();
+    BufReader::new(read.take(MAX_COMMAND))
+        .read_line(&mut command)
+        .await?;
+
+    write.write_all(run(&command).as_bytes()).await?;
+    write.shutdown().await
+}
+
+/// Serve the admin commands on the Unix socket at `path` until `shutdown` is
+/// triggered. A socket left over at `path` is replaced, any other file is kept
+/// and fails the bind; the socket is bound before returning, so a bad path is
+/// reported right away.
+pub fn spawn_socket(path: &Path, shutdown: &Shutdown) -> std::io::Result<()> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    info!(path = %path.display(), "serving admin commands");
+
+    let shutdown = shutdown.clone();
+    let path = path.to_path_buf();
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                _ = shutdown.triggered() => break,
+                accepted = listener.accept() => accepted,
+            };
+
+            match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = answer(stream).await {
+                            debug!(error = %e, "failed to answer an admin command");
+                        }
+                    });
+                }
+                Err(e) => warn!(error = %e, "failed to accept an admin connection"),
+            }
+        }
+        // Bound before the privileges are dropped, the socket may sit in a
+        // directory the server can no longer write to
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!(path = %path.display(), error = %e, "failed to remove the admin socket");
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::cmp::min;
+use std::io::{Read, Write};
+
+use crate::io::{ReadError, CHUNK_SIZE};
+
+/// Send the whole buffer to the stream, retrying on partial writes.
+pub fn send_to_socket<W: Write>(stream: &mut W, buff: &[u8]) -> std::io::Result<()> {
+    stream.write_all(buff)?;
+    stream.flush()
+}
+
+/// Read exactly `N` bytes from the stream, for protocols with fixed-size frames.
+pub fn read_frame<const N: usize, R: Read>(stream: &mut R) -> Result<[u8; N], ReadError> {
+    let mut buff: [u8; N] = [0; N];
+    let mut w = 0;
+
+    while w < N {
+        w += match stream.read(&mut buff[w..]) {
+            Ok(0) => return Err(ReadError::Eof),
+            Err(e) => return Err(ReadError::Io(e)),
+            Ok(n) => n,
+        };
+    }
+
+    Ok(buff)
+}
+
+/// Blocking version of [`crate::io::LineReader`].
+pub struct LineReader<R> {
+    inner: R,
+    buff: Vec<u8>,
+    max: usize,
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(inner: R, max: usize) -> LineReader<R> {
+        LineReader {
+            inner,
+            buff: Vec::with_capacity(min(max, CHUNK_SIZE)),
+            max,
+        }
+    }
+
+    /// Access the underlying stream, e.g. to write a reply on it.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Read the next line, without its trailing newline.
+    pub fn read_line(&mut self) -> Result<Vec<u8>, ReadError> {
+        let mut searched = 0;
+
+        loop {
+            if let Some(idx) = self.buff[searched..].iter().position(|&b| b == b'\n') {
+                let end = searched + idx;
+                let mut line: Vec<u8> = self.buff.drain(..=end).collect();
+                line.pop();
+                return Ok(line);
+            }
+
+            searched = self.buff.len();
+            if searched >= self.max {
+                return Err(ReadError::TooLong);
+            }
+
+            let mut chunk: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+            let want = min(CHUNK_SIZE, self.max - searched);
+            let n = match self.inner.read(&mut chunk[..want]) {
+                Ok(0) => return Err(ReadError::Eof),
+                Err(e) => return Err(ReadError::Io(e)),
+                Ok(n) => n,
+            };
+
+            self.buff.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Read the next line as a string, without its trailing newline.
+    pub fn read_line_utf8(&mut self) -> Result<String, ReadError> {
+        let line = self.read_line()?;
+        String::from_utf8(line).map_err(|_| ReadError::NotUtf8)
+    }
+}
@@ -0,0 +1,4 @@
+//! Thread-per-connection counterparts of [`crate::io`] and [`crate::net`].
+
+pub mod io;
+pub mod net;
@@ -0,0 +1,106 @@
+use std::fmt::Display;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use tracing::{debug, info, info_span, warn};
+
+/// Run `serve` on every item, each but the last on its own thread, and return
+/// the first error.
+fn serve_each<T, F>(items: Vec<T>, serve: F) -> std::io::Result<()>
+where
+    T: Send + 'static,
+    F: Fn(T) -> std::io::Result<()> + Send + Sync + 'static,
+{
+    let serve = Arc::new(serve);
+    let mut items = items;
+
+    let Some(last) = items.pop() else {
+        return Ok(());
+    };
+
+    let handles: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            let serve = Arc::clone(&serve);
+            thread::spawn(move || serve(item))
+        })
+        .collect();
+
+    serve(last)?;
+
+    for handle in handles {
+        handle.join().expect("Listener thread panicked")?;
+    }
+
+    Ok(())
+}
+
+/// Accept connections on every listener forever, handling each connection on
+/// its own thread. A handler failing only drops its own client; the error is
+/// logged.
+pub fn serve_tcp<H, E>(listeners: Vec<TcpListener>, handler: H) -> std::io::Result<()>
+where
+    H: Fn(TcpStream) -> Result<(), E> + Send + Sync + 'static,
+    E: Display,
+{
+    let handler = Arc::new(handler);
+
+    serve_each(listeners, move |listener| {
+        info!(addr = %listener.local_addr()?, "listening");
+        for stream in listener.incoming().flatten() {
+            let handler = Arc::clone(&handler);
+            let Ok(peer) = stream.peer_addr() else {
+                continue;
+            };
+            thread::spawn(move || {
+                let _span = info_span!("conn", %peer).entered();
+                info!("connected");
+                match handler(stream) {
+                    Ok(()) => info!("disconnected"),
+                    Err(e) => warn!(error = %e, "dropping client"),
+                }
+            });
+        }
+
+        Ok(())
+    })
+}
+
+/// Receive datagrams of up to `max_size` bytes on every socket forever. The
+/// handler returns the reply to send back to the sender, if any. A bad
+/// datagram is logged and skipped.
+pub fn serve_udp<H, E>(sockets: Vec<UdpSocket>, max_size: usize, handler: H) -> std::io::Result<()>
+where
+    H: Fn(&[u8], SocketAddr) -> Result<Option<Vec<u8>>, E> + Send + Sync + 'static,
+    E: Display,
+{
+    serve_each(sockets, move |socket| {
+        info!(addr = %socket.local_addr()?, "listening");
+        let mut recv_buff: Vec<u8> = vec![0; max_size];
+
+        loop {
+            let (sz, addr) = match socket.recv_from(&mut recv_buff) {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!(error = %e, "failed to receive a datagram");
+                    continue;
+                }
+            };
+
+            debug!(peer = %addr, size = sz, "received a datagram");
+            let reply = match handler(&recv_buff[..sz], addr) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    warn!(peer = %addr, error = %e, "ignoring datagram");
+                    continue;
+                }
+            };
+
+            if let Some(reply) = reply {
+                if let Err(e) = socket.send_to(&reply, addr) {
+                    warn!(peer = %addr, error = %e, "failed to reply");
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,402 @@
+//! Command line flags shared by every server binary.
+
+use clap::{Args, Parser, ValueEnum};
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::activation::Activation;
+use crate::limits::Limits;
+use crate::logging::LogFormat;
+use crate::net::{bind_tcp, bind_udp, bind_unix, bind_unix_datagram};
+use crate::privileges;
+use crate::record::Recorder;
+use crate::tls::TlsArgs;
+
+/// Which IP families to listen on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Family {
+    /// IPv4 only
+    #[default]
+    Ipv4,
+    /// IPv6 only
+    Ipv6,
+    /// IPv6 sockets that also accept IPv4 connections
+    Dual,
+}
+
+/// Which IP protocol a `--listen` entry asks for, from a `tcp://` or `udp://`
+/// prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpProtocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for IpProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpProtocol::Tcp => f.write_str("TCP"),
+            IpProtocol::Udp => f.write_str("UDP"),
+        }
+    }
+}
+
+/// Which kind of Unix socket a `--listen` entry asks for, from a `unix://` or
+/// `unixgram://` prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnixType {
+    Stream,
+    Datagram,
+}
+
+/// An entry of `--listen`: an IP address, optionally with its own port, or
+/// the path of a Unix socket.
+///
+/// IP addresses without a prefix are listened on with the protocol of the
+/// service; the other kinds of socket are only taken by the services that
+/// speak them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Ip(Option<IpProtocol>, IpAddr),
+    Socket(Option<IpProtocol>, SocketAddr),
+    Unix(UnixType, PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for (scheme, ty) in [
+            ("unix://", UnixType::Stream),
+            ("unixgram://", UnixType::Datagram),
+        ] {
+            if let Some(path) = s.strip_prefix(scheme) {
+                if path.is_empty() {
+                    return Err(format!("`{s}` lacks the path of the socket"));
+                }
+                return Ok(ListenAddr::Unix(ty, path.into()));
+            }
+        }
+
+        let (protocol, addr) = match (s.strip_prefix("tcp://"), s.strip_prefix("udp://")) {
+            (Some(addr), _) => (Some(IpProtocol::Tcp), addr),
+            (_, Some(addr)) => (Some(IpProtocol::Udp), addr),
+            (None, None) => (None, s),
+        };
+
+        if let Ok(addr) = addr.parse() {
+            return Ok(ListenAddr::Socket(protocol, addr));
+        }
+
+        // Accept bracketed IPv6 addresses without a port too, e.g. `[::1]`
+        let ip = addr
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .unwrap_or(addr);
+
+        ip.parse()
+            .map(|ip| ListenAddr::Ip(protocol, ip))
+            .map_err(|_| {
+                format!("`{s}` is neither an IP address, an IP:PORT pair nor a unix:// path")
+            })
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = |protocol| match protocol {
+            None => "",
+            Some(IpProtocol::Tcp) => "tcp://",
+            Some(IpProtocol::Udp) => "udp://",
+        };
+
+        match self {
+            ListenAddr::Ip(protocol, ip) => write!(f, "{}{ip}", scheme(*protocol)),
+            ListenAddr::Socket(protocol, addr) => write!(f, "{}{addr}", scheme(*protocol)),
+            ListenAddr::Unix(UnixType::Stream, path) => write!(f, "unix://{}", path.display()),
+            ListenAddr::Unix(UnixType::Datagram, path) => {
+                write!(f, "unixgram://{}", path.display())
+            }
+        }
+    }
+}
+
+/// The sockets bound for a server, of every kind its listen addresses ask
+/// for. They are std ones so either runtime can serve them.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    pub tcp: Vec<TcpListener>,
+    pub udp: Vec<UdpSocket>,
+    pub unix: Vec<UnixListener>,
+    pub unix_datagram: Vec<UnixDatagram>,
+}
+
+/// Where a server listens.
+///
+/// Without `--listen`, the server binds the unspecified address of the chosen
+/// family. Each `--listen` entry creates a separate listener, so a server can
+/// listen on several addresses and ports at once.
+#[derive(Args, Clone, Debug, PartialEq, Eq)]
+pub struct ListenArgs {
+    /// Address to listen on, as IP or IP:PORT, with a tcp:// or udp:// prefix to pick the
+    /// protocol, or a Unix socket as unix://PATH or unixgram://PATH. Only the echo takes
+    /// another protocol than its own. May be repeated or comma separated
+    #[arg(
+        short,
+        long = "listen",
+        env = "PROTOHACKER_LISTEN",
+        value_delimiter = ',',
+        value_name = "ADDR"
+    )]
+    pub listen: Vec<ListenAddr>,
+
+    /// Port for listen addresses given without one
+    #[arg(short, long, env = "PROTOHACKER_PORT", default_value_t = 80)]
+    pub port: u16,
+
+    /// IP family to listen on
+    #[arg(long, env = "PROTOHACKER_FAMILY", value_enum, default_value_t)]
+    pub family: Family,
+}
+
+impl ListenArgs {
+    /// The same listen addresses, on another default port.
+    pub fn with_port(&self, port: u16) -> ListenArgs {
+        ListenArgs {
+            port,
+            ..self.clone()
+        }
+    }
+
+    /// Every IP socket address to bind, with its protocol, `protocol` for
+    /// the addresses given without one. Unix sockets are left out.
+    pub fn addrs(&self, protocol: IpProtocol) -> Vec<(IpProtocol, SocketAddr)> {
+        if self.listen.is_empty() {
+            let ip: IpAddr = match self.family {
+                Family::Ipv4 => Ipv4Addr::UNSPECIFIED.into(),
+                Family::Ipv6 | Family::Dual => Ipv6Addr::UNSPECIFIED.into(),
+            };
+            return vec![(protocol, SocketAddr::new(ip, self.port))];
+        }
+
+        self.listen
+            .iter()
+            .filter_map(|addr| match *addr {
+                ListenAddr::Ip(given, ip) => {
+                    Some((given.unwrap_or(protocol), SocketAddr::new(ip, self.port)))
+                }
+                ListenAddr::Socket(given, addr) => Some((given.unwrap_or(protocol), addr)),
+                ListenAddr::Unix(..) => None,
+            })
+            .collect()
+    }
+
+    /// Every address to bind: the IP ones with their protocol and port, as
+    /// in [`ListenArgs::addrs`], then the Unix sockets.
+    pub fn resolved(&self, protocol: IpProtocol) -> Vec<ListenAddr> {
+        let ip = self.addrs(protocol).into_iter();
+        let ip = ip.map(|(protocol, addr)| ListenAddr::Socket(Some(protocol), addr));
+        let unix = self
+            .listen
+            .iter()
+            .filter(|addr| matches!(addr, ListenAddr::Unix(..)))
+            .cloned();
+
+        ip.chain(unix).collect()
+    }
+
+    /// Whether IPv6 sockets should refuse IPv4-mapped connections.
+    fn v6_only(&self) -> bool {
+        self.family != Family::Dual
+    }
+
+    /// Bind every listen address, or take the sockets systemd passed for it.
+    /// IP addresses without a protocol are bound with `protocol`.
+    pub fn bind(
+        &self,
+        protocol: IpProtocol,
+        activation: &mut Activation,
+    ) -> std::io::Result<Listeners> {
+        let mut listeners = Listeners::default();
+
+        for (protocol, addr) in self.addrs(protocol) {
+            match protocol {
+                IpProtocol::Tcp => {
+                    let inherited = activation.take_tcp(addr);
+                    if inherited.is_empty() {
+                        listeners.tcp.push(bind_tcp(addr, self.v6_only())?);
+                    }
+                    listeners.tcp.extend(inherited);
+                }
+                IpProtocol::Udp => {
+                    let inherited = activation.take_udp(addr);
+                    if inherited.is_empty() {
+                        listeners.udp.push(bind_udp(addr, self.v6_only())?);
+                    }
+                    listeners.udp.extend(inherited);
+                }
+            }
+        }
+
+        for addr in &self.listen {
+            match addr {
+                ListenAddr::Unix(UnixType::Stream, path) => listeners.unix.push(bind_unix(path)?),
+                ListenAddr::Unix(UnixType::Datagram, path) => {
+                    listeners.unix_datagram.push(bind_unix_datagram(path)?)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(listeners)
+    }
+
+    /// Refuse the listen addresses asking for another kind of socket than
+    /// `protocol`, for the services that only speak it.
+    fn only(&self, protocol: IpProtocol) -> std::io::Result<()> {
+        let other = self.listen.iter().find(|addr| match addr {
+            ListenAddr::Ip(given, _) | ListenAddr::Socket(given, _) => {
+                given.is_some_and(|given| given != protocol)
+            }
+            ListenAddr::Unix(..) => true,
+        });
+
+        match other {
+            Some(addr) => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot listen on {addr}, the service only speaks {protocol}"),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Bind every listen address, or take the listeners systemd passed for it.
+    pub fn bind_tcp(&self, activation: &mut Activation) -> std::io::Result<Vec<TcpListener>> {
+        self.only(IpProtocol::Tcp)?;
+        Ok(self.bind(IpProtocol::Tcp, activation)?.tcp)
+    }
+
+    /// Bind every listen address, or take the sockets systemd passed for it.
+    pub fn bind_udp(&self, activation: &mut Activation) -> std::io::Result<Vec<UdpSocket>> {
+        self.only(IpProtocol::Udp)?;
+        Ok(self.bind(IpProtocol::Udp, activation)?.udp)
+    }
+}
+
+/// Everything about a single server: where it listens, how many clients it
+/// takes and whether it speaks TLS.
+#[derive(Args, Clone, Debug)]
+pub struct ServerArgs {
+    #[command(flatten)]
+    pub listen: ListenArgs,
+
+    #[command(flatten)]
+    pub limits: Limits,
+
+    #[command(flatten)]
+    pub tls: TlsArgs,
+}
+
+/// Parse a duration given in seconds, optionally with an `s` or `ms` suffix.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("`{s}` is not a duration, expected e.g. `5`, `5s` or `500ms`");
+
+    if let Some(ms) = s.strip_suffix("ms") {
+        return ms
+            .trim()
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| invalid());
+    }
+
+    let secs = s.strip_suffix('s').unwrap_or(s).trim();
+    secs.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(invalid)
+}
+
+/// Settings of the whole process rather than of a single server.
+#[derive(Args, Clone, Debug)]
+pub struct RuntimeArgs {
+    /// How long to wait for open connections to finish after SIGINT/SIGTERM
+    #[arg(
+        long,
+        env = "PROTOHACKER_GRACE_PERIOD",
+        default_value = "5s",
+        value_parser = parse_duration,
+        global = true,
+    )]
+    pub grace_period: Duration,
+
+    /// Format of the log lines. What gets logged is set with PROTOHACKER_LOG or
+    /// RUST_LOG, e.g. `debug` or `protohacker3=trace`
+    #[arg(
+        long,
+        env = "PROTOHACKER_LOG_FORMAT",
+        value_enum,
+        default_value_t,
+        global = true
+    )]
+    pub log_format: LogFormat,
+
+    /// Serve Prometheus metrics on http://ADDR/metrics
+    #[arg(long, env = "PROTOHACKER_METRICS", value_name = "ADDR", global = true)]
+    pub metrics: Option<SocketAddr>,
+
+    /// Serve admin commands on the Unix socket at PATH: list connections, kick a client,
+    /// dump a service, change the log filter
+    #[arg(long, env = "PROTOHACKER_ADMIN", value_name = "PATH", global = true)]
+    pub admin: Option<PathBuf>,
+
+    /// Switch to this user once everything is bound, e.g. to bind port 80 as root and
+    /// serve as `nobody`
+    #[arg(long, env = "PROTOHACKER_USER", value_name = "USER", global = true)]
+    pub user: Option<String>,
+
+    /// Switch to this group once everything is bound, the primary group of --user by
+    /// default
+    #[arg(long, env = "PROTOHACKER_GROUP", value_name = "GROUP", global = true)]
+    pub group: Option<String>,
+
+    /// Record the bytes exchanged with every client to FILE, for `protohacker replay`
+    #[arg(long, env = "PROTOHACKER_RECORD", value_name = "FILE", global = true)]
+    pub record: Option<PathBuf>,
+}
+
+impl RuntimeArgs {
+    /// The recorder asked for with `--record`, if any.
+    pub fn recorder(&self) -> std::io::Result<Option<Arc<Recorder>>> {
+        self.record.as_deref().map(Recorder::create).transpose()
+    }
+
+    /// Warn about the sockets systemd passed that no server took, and switch
+    /// to `--user` and `--group`. Call once every socket and file the servers
+    /// need is open.
+    pub fn drop_privileges(&self, activation: &Activation) -> std::io::Result<()> {
+        activation.warn_unclaimed();
+        privileges::drop_to(self.user.as_deref(), self.group.as_deref())
+    }
+}
+
+/// Flags of a single-server binary.
+#[derive(Parser, Clone, Debug)]
+pub struct Cli {
+    #[command(flatten)]
+    pub server: ServerArgs,
+
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+impl Cli {
+    pub fn parse_cli() -> Cli {
+        Cli::parse()
+    }
+}
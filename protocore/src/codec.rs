@@ -0,0 +1,238 @@
+//! How the bytes of a stream are cut into messages, and how replies are
+//! framed on the way back.
+//!
+//! A [`Codec`] only deals with a buffer; [`Framed`] does the reading, the
+//! buffering and the deadlines around it, so a new framing is a `decode` and
+//! an `encode`.
+
+use std::cmp::min;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::Instant;
+
+use crate::io::{ReadError, CHUNK_SIZE};
+use crate::limits::Timeouts;
+
+/// A framing of messages.
+pub trait Codec: Send + Sync + 'static {
+    /// A message, as handed to the protocol.
+    type Message: Send;
+
+    /// Take the next message out of the front of `buff`, leaving whatever
+    /// follows it. `Ok(None)` asks for more bytes.
+    fn decode(&mut self, buff: &mut Vec<u8>) -> Result<Option<Self::Message>, ReadError>;
+
+    /// Append `reply`, framed, to `out`.
+    fn encode(&self, reply: &[u8], out: &mut Vec<u8>);
+}
+
+/// Newline delimited messages of at most `max` bytes, newline included. The
+/// newline is stripped from messages and added to replies.
+pub struct LineCodec {
+    max: usize,
+    /// How much of the buffer is known to hold no newline.
+    searched: usize,
+}
+
+impl LineCodec {
+    pub fn new(max: usize) -> LineCodec {
+        LineCodec { max, searched: 0 }
+    }
+}
+
+impl Codec for LineCodec {
+    type Message = Vec<u8>;
+
+    fn decode(&mut self, buff: &mut Vec<u8>) -> Result<Option<Vec<u8>>, ReadError> {
+        let window = min(buff.len(), self.max);
+
+        if let Some(idx) = buff[self.searched..window].iter().position(|&b| b == b'\n') {
+            let end = self.searched + idx;
+            let mut line: Vec<u8> = buff.drain(..=end).collect();
+            line.pop();
+            self.searched = 0;
+            return Ok(Some(line));
+        }
+
+        if buff.len() >= self.max {
+            return Err(ReadError::TooLong);
+        }
+
+        self.searched = window;
+        Ok(None)
+    }
+
+    fn encode(&self, reply: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(reply);
+        out.push(b'\n');
+    }
+}
+
+/// Messages of exactly `N` bytes. Replies are sent as they are: fixed-size
+/// protocols give their replies a size of their own.
+#[derive(Default)]
+pub struct FixedFrameCodec<const N: usize>;
+
+impl<const N: usize> Codec for FixedFrameCodec<N> {
+    type Message = [u8; N];
+
+    fn decode(&mut self, buff: &mut Vec<u8>) -> Result<Option<[u8; N]>, ReadError> {
+        if buff.len() < N {
+            return Ok(None);
+        }
+
+        let mut frame = [0; N];
+        frame.copy_from_slice(&buff[..N]);
+        buff.drain(..N);
+        Ok(Some(frame))
+    }
+
+    fn encode(&self, reply: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(reply);
+    }
+}
+
+/// Messages preceded by their length, as a big endian `u32`, of at most
+/// `max` bytes. Replies get the same prefix.
+pub struct LengthPrefixedCodec {
+    max: usize,
+}
+
+impl LengthPrefixedCodec {
+    const PREFIX: usize = 4;
+
+    pub fn new(max: usize) -> LengthPrefixedCodec {
+        LengthPrefixedCodec { max }
+    }
+}
+
+impl Codec for LengthPrefixedCodec {
+    type Message = Vec<u8>;
+
+    fn decode(&mut self, buff: &mut Vec<u8>) -> Result<Option<Vec<u8>>, ReadError> {
+        let Some(prefix) = buff.first_chunk::<{ Self::PREFIX }>() else {
+            return Ok(None);
+        };
+
+        let len = u32::from_be_bytes(*prefix) as usize;
+        if len > self.max {
+            return Err(ReadError::TooLong);
+        }
+        if buff.len() < Self::PREFIX + len {
+            return Ok(None);
+        }
+
+        let message = buff[Self::PREFIX..Self::PREFIX + len].to_vec();
+        buff.drain(..Self::PREFIX + len);
+        Ok(Some(message))
+    }
+
+    fn encode(&self, reply: &[u8], out: &mut Vec<u8>) {
+        let len = u32::try_from(reply.len()).expect("replies are smaller than 4GiB");
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(reply);
+    }
+}
+
+/// Each datagram is a message of at most `max_size` bytes, longer ones being
+/// truncated. Replies are sent as they are.
+///
+/// Datagrams are handed to the protocol as they arrive, empty ones included;
+/// decoding only applies when the codec frames a stream, where it takes
+/// whatever was received.
+pub struct DatagramCodec {
+    max_size: usize,
+}
+
+impl DatagramCodec {
+    pub fn new(max_size: usize) -> DatagramCodec {
+        DatagramCodec { max_size }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+impl Codec for DatagramCodec {
+    type Message = Vec<u8>;
+
+    fn decode(&mut self, buff: &mut Vec<u8>) -> Result<Option<Vec<u8>>, ReadError> {
+        if buff.is_empty() {
+            return Ok(None);
+        }
+
+        buff.truncate(self.max_size);
+        Ok(Some(std::mem::take(buff)))
+    }
+
+    fn encode(&self, reply: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(reply);
+    }
+}
+
+/// Reads the messages of a codec off a stream.
+///
+/// Bytes received past the end of a message are kept for the next one.
+/// Reading a message is cancel safe: if the future is dropped, no received
+/// byte is lost and the next call picks up where it left off.
+pub struct Framed<R, C> {
+    inner: R,
+    codec: C,
+    buff: Vec<u8>,
+    timeouts: Timeouts,
+    /// When the first byte of the message in `buff` arrived.
+    started: Option<Instant>,
+}
+
+impl<R: AsyncRead + Unpin, C: Codec> Framed<R, C> {
+    pub fn new(inner: R, codec: C) -> Framed<R, C> {
+        Framed {
+            inner,
+            codec,
+            buff: Vec::with_capacity(CHUNK_SIZE),
+            timeouts: Timeouts::default(),
+            started: None,
+        }
+    }
+
+    /// Bound the reads by the deadlines of the connection.
+    pub fn with_timeouts(self, timeouts: Timeouts) -> Framed<R, C> {
+        Framed { timeouts, ..self }
+    }
+
+    /// Access the underlying stream, e.g. to write a reply on it.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// The stream, to write on, along with the codec, to frame what is written.
+    pub fn parts_mut(&mut self) -> (&mut R, &C) {
+        (&mut self.inner, &self.codec)
+    }
+
+    /// Read the next message. The stream closing between two messages is
+    /// [`ReadError::Eof`], as is it closing in the middle of one.
+    pub async fn read_message(&mut self) -> Result<C::Message, ReadError> {
+        loop {
+            if let Some(message) = self.codec.decode(&mut self.buff)? {
+                // Leftover bytes are the start of the next message
+                self.started = (!self.buff.is_empty()).then(Instant::now);
+                return Ok(message);
+            }
+
+            let mut chunk: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+            let read = self.inner.read(&mut chunk);
+            let n = match self.timeouts.limit(self.started, read).await? {
+                0 => return Err(ReadError::Eof),
+                n => n,
+            };
+
+            self.started.get_or_insert_with(Instant::now);
+            self.buff.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+use crate::codec::{Framed, LineCodec};
+use crate::limits::Timeouts;
+
+/// Failure to read a message off a stream.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The peer closed the stream, possibly in the middle of a message.
+    Eof,
+    /// The message is larger than the reader accepts.
+    TooLong,
+    /// A line reader was asked for text and got something else.
+    NotUtf8,
+    /// No message was started within the idle timeout.
+    Idle(Duration),
+    /// A message was started but not completed within the read timeout.
+    Deadline(Duration),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Eof => write!(f, "the client has disconnected"),
+            ReadError::TooLong => write!(f, "the message is too large"),
+            ReadError::NotUtf8 => write!(f, "the message is not valid UTF-8"),
+            ReadError::Idle(d) => write!(f, "no message received for {d:?}"),
+            ReadError::Deadline(d) => write!(f, "the message took longer than {d:?} to arrive"),
+            ReadError::Io(e) => write!(f, "read failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ReadError {
+    fn from(e: std::io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// For handlers that only deal in I/O errors, such as echo.
+impl From<ReadError> for std::io::Error {
+    fn from(e: ReadError) -> Self {
+        use std::io::ErrorKind;
+
+        let kind = match e {
+            ReadError::Io(e) => return e,
+            ReadError::Eof => ErrorKind::UnexpectedEof,
+            ReadError::TooLong | ReadError::NotUtf8 => ErrorKind::InvalidData,
+            ReadError::Idle(_) | ReadError::Deadline(_) => ErrorKind::TimedOut,
+        };
+
+        std::io::Error::new(kind, e)
+    }
+}
+
+/// Size of a single read from the underlying stream.
+pub(crate) const CHUNK_SIZE: usize = 1024;
+
+/// Send the whole buffer to the stream, retrying on partial writes.
+pub async fn send_to_socket<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    buff: &[u8],
+) -> std::io::Result<()> {
+    stream.write_all(buff).await?;
+    stream.flush().await
+}
+
+/// Read exactly `N` bytes from the stream, for protocols with fixed-size frames.
+pub async fn read_frame<const N: usize, R: AsyncRead + Unpin>(
+    stream: &mut R,
+    timeouts: &Timeouts,
+) -> Result<[u8; N], ReadError> {
+    let mut buff: [u8; N] = [0; N];
+    let mut w = 0;
+    let mut started = None;
+
+    while w < N {
+        w += match timeouts.limit(started, stream.read(&mut buff[w..])).await? {
+            0 => return Err(ReadError::Eof),
+            n => n,
+        };
+        started.get_or_insert_with(Instant::now);
+    }
+
+    Ok(buff)
+}
+
+/// Newline-delimited reader over a stream.
+///
+/// Bytes received after a newline are kept for the next call, so several lines
+/// arriving in a single read are not lost. A line longer than `max` bytes
+/// (newline included) is an error, as is the stream closing mid-line.
+///
+/// Reading a line is cancel safe: if the future is dropped, no received byte is
+/// lost and the next call picks up where it left off.
+pub struct LineReader<R> {
+    framed: Framed<R, LineCodec>,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    pub fn new(inner: R, max: usize) -> LineReader<R> {
+        LineReader {
+            framed: Framed::new(inner, LineCodec::new(max)),
+        }
+    }
+
+    /// Bound the reads by the deadlines of the connection.
+    pub fn with_timeouts(self, timeouts: Timeouts) -> LineReader<R> {
+        LineReader {
+            framed: self.framed.with_timeouts(timeouts),
+        }
+    }
+
+    /// Access the underlying stream, e.g. to write a reply on it.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.framed.get_mut()
+    }
+
+    /// Read the next line, without its trailing newline.
+    pub async fn read_line(&mut self) -> Result<Vec<u8>, ReadError> {
+        self.framed.read_message().await
+    }
+
+    /// Read the next line as a string, without its trailing newline.
+    pub async fn read_line_utf8(&mut self) -> Result<String, ReadError> {
+        let line = self.read_line().await?;
+        String::from_utf8(line).map_err(|_| ReadError::NotUtf8)
+    }
+}
@@ -0,0 +1,26 @@
+//! Plumbing shared by every protohacker server: accept loops, framed readers
+//! and write helpers. Each server only has to provide its protocol handler,
+//! or for request/response protocols, a [`protocol::Protocol`].
+//!
+//! Servers run on a tokio runtime. The original thread-per-connection
+//! implementation is kept in [`blocking`] behind the `blocking` feature, for
+//! comparison.
+
+pub mod activation;
+pub mod admin;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cli;
+pub mod codec;
+pub mod io;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod net;
+pub mod privileges;
+pub mod protocol;
+pub mod record;
+pub mod reload;
+pub mod shutdown;
+pub mod stream;
+pub mod tls;
@@ -0,0 +1,217 @@
+//! Connection caps and read deadlines, enforced by the accept loop and the
+//! framed readers so a handful of clients cannot hold a server hostage.
+
+use clap::Args;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::cli::parse_duration;
+use crate::io::ReadError;
+use crate::metrics::{self, Counter};
+use crate::reload::Reloadable;
+
+/// Limits of a TCP server. Every limit is off unless set.
+#[derive(Args, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of clients connected at once
+    #[arg(long, env = "PROTOHACKER_MAX_CLIENTS", value_name = "N")]
+    pub max_clients: Option<usize>,
+
+    /// Maximum number of clients connected at once from a single IP address
+    #[arg(long, env = "PROTOHACKER_MAX_CLIENTS_PER_IP", value_name = "N")]
+    pub max_clients_per_ip: Option<usize>,
+
+    /// Disconnect clients that do not start a new message for this long
+    #[arg(long, env = "PROTOHACKER_IDLE_TIMEOUT", value_parser = parse_duration)]
+    pub idle_timeout: Option<Duration>,
+
+    /// Disconnect clients that take longer than this to send a whole message
+    #[arg(long, env = "PROTOHACKER_READ_TIMEOUT", value_parser = parse_duration)]
+    pub read_timeout: Option<Duration>,
+}
+
+/// How many connections the limits turned away, for a single server.
+#[derive(Debug, Default)]
+pub struct LimitStats {
+    rejected: Arc<Counter>,
+    timed_out: Arc<Counter>,
+}
+
+impl LimitStats {
+    /// Counters of the service `name`, exported as metrics.
+    pub(crate) fn registered(name: &str) -> LimitStats {
+        let labels = [("service", name)];
+
+        LimitStats {
+            rejected: metrics::counter(
+                "protohacker_connections_rejected_total",
+                "Connections closed on accept because of a connection cap.",
+                &labels,
+            ),
+            timed_out: metrics::counter(
+                "protohacker_connections_timed_out_total",
+                "Connections dropped because a read deadline expired.",
+                &labels,
+            ),
+        }
+    }
+
+    /// Connections closed right after being accepted, because of a cap.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.get()
+    }
+
+    /// Connections dropped because a read deadline expired.
+    pub fn timed_out(&self) -> u64 {
+        self.timed_out.get()
+    }
+
+    pub(crate) fn count_rejected(&self) -> u64 {
+        self.rejected.inc();
+        self.rejected.get()
+    }
+}
+
+/// Why a connection was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// `max_clients` clients are already connected.
+    Full(usize),
+    /// `max_clients_per_ip` clients are already connected from this address.
+    PerIp(usize),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Full(max) => write!(f, "already {max} clients connected"),
+            Rejection::PerIp(max) => write!(f, "already {max} clients from this address"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Connected {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Counts the open connections of a server against its caps. Lowered caps
+/// only turn away new clients, the ones over them stay connected.
+pub(crate) struct ConnectionLimiter {
+    limits: Reloadable<Limits>,
+    connected: Mutex<Connected>,
+}
+
+impl ConnectionLimiter {
+    pub(crate) fn new(limits: Reloadable<Limits>) -> ConnectionLimiter {
+        ConnectionLimiter {
+            limits,
+            connected: Mutex::new(Connected::default()),
+        }
+    }
+
+    /// Count a new client from `ip`, unless a cap is reached. Clients of a
+    /// Unix socket come from no address and only count against `max_clients`.
+    /// The client is counted until the returned permit is dropped.
+    pub(crate) fn acquire(self: &Arc<Self>, ip: Option<IpAddr>) -> Result<Permit, Rejection> {
+        // Clients of a dual stack listener show up as IPv4-mapped addresses
+        let ip = ip.map(|ip| ip.to_canonical());
+        let limits = self.limits.get();
+        let mut connected = self.connected.lock().unwrap();
+
+        if let Some(max) = limits.max_clients {
+            if connected.total >= max {
+                return Err(Rejection::Full(max));
+            }
+        }
+
+        if let Some(ip) = ip {
+            let from_ip = connected.per_ip.get(&ip).copied().unwrap_or(0);
+            if let Some(max) = limits.max_clients_per_ip {
+                if from_ip >= max {
+                    return Err(Rejection::PerIp(max));
+                }
+            }
+            connected.per_ip.insert(ip, from_ip + 1);
+        }
+        connected.total += 1;
+
+        Ok(Permit {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: Option<IpAddr>) {
+        let mut connected = self.connected.lock().unwrap();
+        connected.total -= 1;
+
+        let Some(ip) = ip else {
+            return;
+        };
+        if let Some(count) = connected.per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                connected.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// A connection counted by a [`ConnectionLimiter`].
+pub(crate) struct Permit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+/// Read deadlines of a connection.
+///
+/// The idle timeout bounds the wait for the first byte of a message, the read
+/// timeout the time between that byte and the end of the message. Expired
+/// deadlines are counted in the server's [`LimitStats`]. Changed limits apply
+/// from the next message on.
+#[derive(Clone, Default)]
+pub struct Timeouts {
+    limits: Reloadable<Limits>,
+    stats: Arc<LimitStats>,
+}
+
+impl Timeouts {
+    pub(crate) fn new(limits: Reloadable<Limits>, stats: Arc<LimitStats>) -> Timeouts {
+        Timeouts { limits, stats }
+    }
+
+    /// Run a read of the stream within the deadlines. `started` is when the
+    /// message being read started arriving, or `None` while waiting for it.
+    pub async fn limit<F, T>(&self, started: Option<Instant>, read: F) -> Result<T, ReadError>
+    where
+        F: Future<Output = std::io::Result<T>>,
+    {
+        let limits = self.limits.get();
+        let (deadline, expired) = match (started, limits.idle_timeout, limits.read_timeout) {
+            (None, Some(idle), _) => (Instant::now() + idle, ReadError::Idle(idle)),
+            (Some(started), _, Some(read)) => (started + read, ReadError::Deadline(read)),
+            _ => return Ok(read.await?),
+        };
+
+        match tokio::time::timeout_at(deadline, read).await {
+            Ok(result) => Ok(result?),
+            Err(_) => {
+                self.stats.timed_out.inc();
+                Err(expired)
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Leveled logging through `tracing`.
+//!
+//! The accept loops open a span per connection carrying the service, the
+//! connection id and the peer address, so every event a handler logs can be
+//! traced back to its session.
+
+use clap::ValueEnum;
+use std::sync::OnceLock;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Environment variable holding the log filter, in `RUST_LOG` syntax.
+pub const LOG_ENV: &str = "PROTOHACKER_LOG";
+
+/// Filter used when neither `PROTOHACKER_LOG` nor `RUST_LOG` is set.
+const DEFAULT_FILTER: &str = "info";
+
+/// How log lines are written to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// The filter to start with: `PROTOHACKER_LOG`, then `RUST_LOG`, then `info`.
+fn env_filter() -> EnvFilter {
+    [LOG_ENV, EnvFilter::DEFAULT_ENV]
+        .into_iter()
+        .find_map(|var| EnvFilter::try_from_env(var).ok())
+        .unwrap_or_else(|| EnvFilter::new(DEFAULT_FILTER))
+}
+
+/// Swaps the filter of the global subscriber, once installed.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Install the global subscriber. Call once, before serving anything.
+pub fn init(format: LogFormat) {
+    let (filter, handle) = reload::Layer::new(env_filter());
+    let registry = tracing_subscriber::registry().with(filter);
+
+    match format {
+        LogFormat::Text => registry
+            .with(fmt::layer().with_writer(std::io::stderr))
+            .init(),
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_writer(std::io::stderr),
+            )
+            .init(),
+    }
+
+    let _ = FILTER.set(handle);
+}
+
+/// The filter in use, `None` before [`init`].
+pub fn filter() -> Option<String> {
+    let handle = FILTER.get()?;
+    handle.with_current(|filter| filter.to_string()).ok()
+}
+
+/// Replace the filter in use, e.g. with `debug` or `protohacker3=trace`.
+pub fn set_filter(filter: &str) -> Result<(), String> {
+    let handle = FILTER.get().ok_or("logging is not set up")?;
+    let filter = EnvFilter::try_new(filter).map_err(|e| e.to_string())?;
+    handle.reload(filter).map_err(|e| e.to_string())
+}
@@ -0,0 +1,456 @@
+//! A process-wide registry of counters, gauges and histograms, rendered in the
+//! Prometheus text format and optionally served over HTTP on `/metrics`.
+//!
+//! Metrics are registered on first use and live for the whole process. Hot
+//! paths should keep the returned handle rather than looking it up each time.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+use crate::shutdown::Shutdown;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// A value that only goes up.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Durations sorted into fixed buckets.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// One count per bound, plus the `+Inf` bucket. Not cumulative.
+    buckets: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new(LATENCY_BUCKETS)
+    }
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(self.bounds.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Start timing something; the time is recorded when the timer is dropped.
+    pub fn start_timer(self: &Arc<Self>) -> Timer {
+        Timer {
+            histogram: Arc::clone(self),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+}
+
+/// Records the time since its creation in a histogram when dropped.
+pub struct Timer {
+    histogram: Arc<Histogram>,
+    started: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.histogram.observe(self.started.elapsed());
+    }
+}
+
+#[derive(Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+/// Every series of a metric, keyed by their rendered labels.
+struct Family {
+    help: &'static str,
+    series: BTreeMap<String, Metric>,
+}
+
+/// A set of metrics rendered together.
+#[derive(Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+/// Render labels as `{a="x",b="y"}`, or nothing without labels.
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', r"\\")
+                .replace('"', r#"\""#)
+                .replace('\n', r"\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Add a label to already rendered labels.
+fn with_label(labels: &str, name: &str, value: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(labels) => format!("{labels},{name}=\"{value}\"}}"),
+        None => format!("{{{name}=\"{value}\"}}"),
+    }
+}
+
+impl Registry {
+    /// The series of `name` with these labels, created by `new` if missing.
+    ///
+    /// Panics if `name` was registered as another kind of metric.
+    fn get_or_register(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        new: impl FnOnce() -> Metric,
+    ) -> Metric {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            series: BTreeMap::new(),
+        });
+
+        let metric = family
+            .series
+            .entry(render_labels(labels))
+            .or_insert_with(new)
+            .clone();
+
+        if let Some(other) = family.series.values().find(|m| m.kind() != metric.kind()) {
+            panic!(
+                "metric {name} registered as both a {} and a {}",
+                metric.kind(),
+                other.kind()
+            );
+        }
+
+        metric
+    }
+
+    pub fn counter(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+    ) -> Arc<Counter> {
+        match self.get_or_register(name, help, labels, || Metric::Counter(Arc::default())) {
+            Metric::Counter(counter) => counter,
+            other => panic!("metric {name} is a {}, not a counter", other.kind()),
+        }
+    }
+
+    pub fn gauge(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+    ) -> Arc<Gauge> {
+        match self.get_or_register(name, help, labels, || Metric::Gauge(Arc::default())) {
+            Metric::Gauge(gauge) => gauge,
+            other => panic!("metric {name} is a {}, not a gauge", other.kind()),
+        }
+    }
+
+    /// A histogram with buckets suited to request latencies.
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+    ) -> Arc<Histogram> {
+        match self.get_or_register(name, help, labels, || Metric::Histogram(Arc::default())) {
+            Metric::Histogram(histogram) => histogram,
+            other => panic!("metric {name} is a {}, not a histogram", other.kind()),
+        }
+    }
+
+    /// Every metric, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+
+        for (name, family) in families.iter() {
+            let Some(first) = family.series.values().next() else {
+                continue;
+            };
+
+            let _ = writeln!(out, "# HELP {name} {}", family.help);
+            let _ = writeln!(out, "# TYPE {name} {}", first.kind());
+
+            for (labels, metric) in &family.series {
+                match metric {
+                    Metric::Counter(counter) => {
+                        let _ = writeln!(out, "{name}{labels} {}", counter.get());
+                    }
+                    Metric::Gauge(gauge) => {
+                        let _ = writeln!(out, "{name}{labels} {}", gauge.get());
+                    }
+                    Metric::Histogram(histogram) => {
+                        render_histogram(&mut out, name, labels, histogram)
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn render_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let mut cumulative = 0;
+
+    for (i, count) in histogram.buckets.iter().enumerate() {
+        cumulative += count.load(Ordering::Relaxed);
+        let le = match histogram.bounds.get(i) {
+            Some(bound) => bound.to_string(),
+            None => "+Inf".to_string(),
+        };
+        let labels = with_label(labels, "le", &le);
+        let _ = writeln!(out, "{name}_bucket{labels} {cumulative}");
+    }
+
+    let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+    let _ = writeln!(out, "{name}_sum{labels} {sum}");
+    let _ = writeln!(out, "{name}_count{labels} {cumulative}");
+}
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+/// The registry of the process, served on `/metrics`.
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// A counter of the process registry.
+pub fn counter(name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Arc<Counter> {
+    registry().counter(name, help, labels)
+}
+
+/// A gauge of the process registry.
+pub fn gauge(name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+    registry().gauge(name, help, labels)
+}
+
+/// A latency histogram of the process registry.
+pub fn histogram(
+    name: &'static str,
+    help: &'static str,
+    labels: &[(&str, &str)],
+) -> Arc<Histogram> {
+    registry().histogram(name, help, labels)
+}
+
+/// Largest HTTP request head the endpoint reads.
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// Answer a single HTTP request, then close the connection.
+async fn answer(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut head = Vec::with_capacity(1024);
+    let mut chunk = [0; 1024];
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD {
+            break;
+        }
+
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&chunk[..n]);
+    }
+
+    let request_line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (method, path) = (parts.next(), parts.next());
+
+    let (status, content_type, body) = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => {
+            ("200 OK", "text/plain; version=0.0.4", registry().render())
+        }
+        (Some(b"GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "only GET is supported\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serve the process registry on `http://addr/metrics` until `shutdown` is
+/// triggered. The endpoint is bound before returning, so a busy address is
+/// reported right away.
+pub fn spawn_endpoint(addr: SocketAddr, shutdown: &Shutdown) -> std::io::Result<()> {
+    let listener = crate::net::bind_tcp(addr, true)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    info!(%addr, "serving metrics on /metrics");
+
+    let shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                _ = shutdown.triggered() => return,
+                accepted = listener.accept() => accepted,
+            };
+
+            match accepted {
+                Ok((stream, peer)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = answer(stream).await {
+                            debug!(%peer, error = %e, "failed to answer a metrics request");
+                        }
+                    });
+                }
+                Err(e) => warn!(error = %e, "failed to accept a metrics connection"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Metrics the accept loops keep for every service.
+#[derive(Clone)]
+pub(crate) struct ServiceMetrics {
+    pub(crate) connections: Arc<Counter>,
+    pub(crate) open: Arc<Gauge>,
+    pub(crate) received: Arc<Counter>,
+    pub(crate) sent: Arc<Counter>,
+    pub(crate) datagrams: Arc<Counter>,
+    pub(crate) requests: Arc<Histogram>,
+}
+
+impl ServiceMetrics {
+    pub(crate) fn new(service: &str) -> ServiceMetrics {
+        let labels = [("service", service)];
+
+        ServiceMetrics {
+            connections: counter(
+                "protohacker_connections_total",
+                "Connections accepted.",
+                &labels,
+            ),
+            open: gauge(
+                "protohacker_connections_open",
+                "Connections currently open.",
+                &labels,
+            ),
+            received: counter(
+                "protohacker_received_bytes_total",
+                "Bytes received from clients.",
+                &labels,
+            ),
+            sent: counter(
+                "protohacker_sent_bytes_total",
+                "Bytes sent to clients.",
+                &labels,
+            ),
+            datagrams: counter(
+                "protohacker_datagrams_total",
+                "Datagrams received.",
+                &labels,
+            ),
+            requests: histogram(
+                "protohacker_request_duration_seconds",
+                "Time taken to handle a request, from its arrival to the reply.",
+                &labels,
+            ),
+        }
+    }
+}
@@ -0,0 +1,678 @@
+use socket2::{Domain, Socket, Type};
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::{TcpListener, UdpSocket, UnixDatagram, UnixListener};
+use tokio::task::JoinSet;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::admin;
+use crate::codec::DatagramCodec;
+use crate::io::ReadError;
+use crate::limits::{ConnectionLimiter, LimitStats, Limits, Timeouts};
+use crate::metrics::{ServiceMetrics, Timer};
+use crate::protocol::{handle_datagram, run_session, Protocol};
+use crate::record::{EventKind, Recorder, Recording};
+use crate::reload::Reloadable;
+use crate::shutdown::Shutdown;
+use crate::stream::{Stream, Transport};
+use crate::tls::TlsAcceptor;
+
+/// Backlog of pending connections on TCP listeners.
+const LISTEN_BACKLOG: i32 = 1024;
+
+fn new_socket(
+    addr: SocketAddr,
+    ty: Type,
+    protocol: socket2::Protocol,
+    v6_only: bool,
+) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+
+    Ok(socket)
+}
+
+/// Bind a TCP listener. `v6_only` is ignored for IPv4 addresses.
+///
+/// The listener is a std one so either runtime can serve it.
+pub fn bind_tcp(addr: SocketAddr, v6_only: bool) -> std::io::Result<std::net::TcpListener> {
+    let socket = new_socket(addr, Type::STREAM, socket2::Protocol::TCP, v6_only)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(socket.into())
+}
+
+/// Bind a UDP socket. `v6_only` is ignored for IPv4 addresses.
+///
+/// The socket is a std one so either runtime can serve it.
+pub fn bind_udp(addr: SocketAddr, v6_only: bool) -> std::io::Result<std::net::UdpSocket> {
+    let socket = new_socket(addr, Type::DGRAM, socket2::Protocol::UDP, v6_only)?;
+    socket.bind(&addr.into())?;
+
+    Ok(socket.into())
+}
+
+/// Remove a Unix socket left over at `path` by a previous run, so it can be
+/// bound again. Any other kind of file is kept, and fails the bind.
+pub(crate) fn remove_stale_socket(path: &Path) -> std::io::Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Bind a Unix stream listener at `path`, replacing a socket left over there.
+///
+/// The listener is a std one so either runtime can serve it.
+pub fn bind_unix(path: &Path) -> std::io::Result<std::os::unix::net::UnixListener> {
+    remove_stale_socket(path)?;
+    std::os::unix::net::UnixListener::bind(path)
+}
+
+/// Bind a Unix datagram socket at `path`, replacing a socket left over there.
+///
+/// The socket is a std one so either runtime can serve it.
+pub fn bind_unix_datagram(path: &Path) -> std::io::Result<std::os::unix::net::UnixDatagram> {
+    remove_stale_socket(path)?;
+    std::os::unix::net::UnixDatagram::bind(path)
+}
+
+/// Wait for every task of the set, returning the first error.
+async fn join_all(mut tasks: JoinSet<std::io::Result<()>>) -> std::io::Result<()> {
+    while let Some(result) = tasks.join_next().await {
+        result.expect("Listener task panicked")?;
+    }
+
+    Ok(())
+}
+
+/// Establish TLS on an accepted TCP connection, if the server speaks it. The
+/// handshake is held to the idle timeout.
+async fn handshake(
+    transport: Transport,
+    tls: Option<&TlsAcceptor>,
+    timeouts: &Timeouts,
+) -> Result<Transport, ReadError> {
+    match (transport, tls) {
+        (Transport::Plain(stream), Some(acceptor)) => {
+            let stream = timeouts.limit(None, acceptor.accept(stream)).await?;
+            Ok(Transport::Tls(Box::new(stream)))
+        }
+        (transport, _) => Ok(transport),
+    }
+}
+
+/// Where a client comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Peer {
+    Ip(SocketAddr),
+    /// A client of a Unix socket, which has no address worth telling.
+    Unix,
+}
+
+impl Peer {
+    /// The IP address of the client, unless it came over a Unix socket.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Ip(addr) => Some(addr.ip()),
+            Peer::Unix => None,
+        }
+    }
+}
+
+impl Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Ip(addr) => addr.fmt(f),
+            Peer::Unix => f.write_str("unix"),
+        }
+    }
+}
+
+/// A listener of either kind of stream socket.
+enum StreamListener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl StreamListener {
+    fn tcp(listener: std::net::TcpListener) -> std::io::Result<StreamListener> {
+        listener.set_nonblocking(true)?;
+        Ok(StreamListener::Tcp(TcpListener::from_std(listener)?))
+    }
+
+    fn unix(listener: std::os::unix::net::UnixListener) -> std::io::Result<StreamListener> {
+        listener.set_nonblocking(true)?;
+        let path = listener
+            .local_addr()?
+            .as_pathname()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Ok(StreamListener::Unix(
+            UnixListener::from_std(listener)?,
+            path,
+        ))
+    }
+
+    async fn accept(&self) -> std::io::Result<(Transport, Peer)> {
+        match self {
+            StreamListener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Transport::Plain(stream), Peer::Ip(peer)))
+            }
+            StreamListener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Transport::Unix(stream), Peer::Unix))
+            }
+        }
+    }
+
+    /// Where the listener listens, for the logs.
+    fn local(&self) -> std::io::Result<String> {
+        match self {
+            StreamListener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            StreamListener::Unix(_, path) => Ok(format!("unix://{}", path.display())),
+        }
+    }
+
+    /// Stop listening. The file of a Unix socket is removed, so no client
+    /// connects to a socket nobody listens on.
+    fn close(self) {
+        if let StreamListener::Unix(listener, path) = self {
+            drop(listener);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Ids of the connections accepted by this process, across every server.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What a handler knows about the connection it serves.
+#[derive(Clone)]
+pub struct Context {
+    id: u64,
+    peer: Peer,
+    shutdown: Shutdown,
+    timeouts: Timeouts,
+    metrics: ServiceMetrics,
+}
+
+impl Context {
+    /// Unique id of the connection, as found in the logs.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn peer(&self) -> Peer {
+        self.peer
+    }
+
+    /// Resolves once the server is shutting down. Handlers whose protocol can
+    /// tell the client about it should do so then and wrap up; the others are
+    /// left to finish within the grace period.
+    pub async fn closing(&self) {
+        self.shutdown.triggered().await
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.shutdown.is_triggered()
+    }
+
+    /// Read deadlines of the connection, to hand to the framed readers.
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// Time a request of the service, from its arrival until the timer is
+    /// dropped once the reply is sent.
+    pub fn start_request(&self) -> Timer {
+        self.metrics.requests.start_timer()
+    }
+}
+
+/// The accept loops of a single service, along with the limits they enforce.
+///
+/// Every listener given to the same `Server` shares its connection caps.
+pub struct Server {
+    name: &'static str,
+    shutdown: Shutdown,
+    limits: Reloadable<Limits>,
+    stats: Arc<LimitStats>,
+    metrics: ServiceMetrics,
+    recorder: Option<Arc<Recorder>>,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Server {
+    /// A server for the service `name`, which labels its logs and metrics.
+    pub fn new(name: &'static str, shutdown: &Shutdown) -> Server {
+        Server {
+            name,
+            shutdown: shutdown.clone(),
+            limits: Reloadable::default(),
+            stats: Arc::new(LimitStats::registered(name)),
+            metrics: ServiceMetrics::new(name),
+            recorder: None,
+            tls: None,
+        }
+    }
+
+    /// Enforce connection caps and read deadlines. Only TCP servers have any.
+    /// Limits given as a [`Reloadable`] can be changed while serving.
+    pub fn with_limits(self, limits: impl Into<Reloadable<Limits>>) -> Server {
+        Server {
+            limits: limits.into(),
+            ..self
+        }
+    }
+
+    /// Record the traffic of every client.
+    pub fn with_recorder(self, recorder: Option<Arc<Recorder>>) -> Server {
+        Server { recorder, ..self }
+    }
+
+    /// Terminate TLS on every connection before handing it to the handler.
+    /// Only TCP servers speak TLS.
+    pub fn with_tls(self, tls: Option<TlsAcceptor>) -> Server {
+        Server { tls, ..self }
+    }
+
+    /// Let the admin socket dump the state of the service, e.g. what a store
+    /// holds.
+    pub fn set_dump(&self, dump: impl Fn() -> String + Send + Sync + 'static) {
+        admin::registry().set_dump(self.name, dump);
+    }
+
+    /// How many connections the limits have turned away so far.
+    pub fn stats(&self) -> &LimitStats {
+        &self.stats
+    }
+
+    /// Accept connections on every listener until the shutdown is triggered,
+    /// handling each connection on its own task. A handler failing only drops
+    /// its own client; the error is logged.
+    ///
+    /// Connections over the caps are closed as soon as they are accepted.
+    /// Those accepted are listed on the admin socket until they close, and a
+    /// connection kicked from it has its handler dropped.
+    ///
+    /// Connection tasks are tracked by the shutdown, so they can outlive this
+    /// function until [`Shutdown::drain`] gives up on them.
+    pub async fn serve_tcp<H, Fut, E>(
+        &self,
+        listeners: Vec<std::net::TcpListener>,
+        handler: H,
+    ) -> std::io::Result<()>
+    where
+        H: Fn(Stream, Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let listeners = listeners
+            .into_iter()
+            .map(StreamListener::tcp)
+            .collect::<std::io::Result<_>>()?;
+        self.serve_streams(listeners, handler).await
+    }
+
+    /// Accept connections on every Unix listener, as [`Server::serve_tcp`]
+    /// does. The clients only count against `max_clients`, TLS is not spoken
+    /// and their traffic is not recorded. The socket files are removed once
+    /// the shutdown is triggered.
+    pub async fn serve_unix<H, Fut, E>(
+        &self,
+        listeners: Vec<std::os::unix::net::UnixListener>,
+        handler: H,
+    ) -> std::io::Result<()>
+    where
+        H: Fn(Stream, Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        if self.tls.is_some() && !listeners.is_empty() {
+            warn!(
+                service = self.name,
+                "TLS is only spoken over TCP, serving Unix sockets in plaintext"
+            );
+        }
+
+        let listeners = listeners
+            .into_iter()
+            .map(StreamListener::unix)
+            .collect::<std::io::Result<_>>()?;
+        self.serve_streams(listeners, handler).await
+    }
+
+    async fn serve_streams<H, Fut, E>(
+        &self,
+        listeners: Vec<StreamListener>,
+        handler: H,
+    ) -> std::io::Result<()>
+    where
+        H: Fn(Stream, Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let handler = Arc::new(handler);
+        let limiter = Arc::new(ConnectionLimiter::new(self.limits.clone()));
+        let timeouts = Timeouts::new(self.limits.clone(), Arc::clone(&self.stats));
+        let mut tasks = JoinSet::new();
+
+        for listener in listeners {
+            info!(service = self.name, addr = %listener.local()?, "listening");
+            let handler = Arc::clone(&handler);
+            let limiter = Arc::clone(&limiter);
+            let stats = Arc::clone(&self.stats);
+            let timeouts = timeouts.clone();
+            let shutdown = self.shutdown.clone();
+            let metrics = self.metrics.clone();
+            let recorder = self.recorder.clone();
+            let tls = self.tls.clone();
+            let service = self.name;
+
+            tasks.spawn(async move {
+                loop {
+                    let accepted = tokio::select! {
+                        _ = shutdown.triggered() => {
+                            listener.close();
+                            return Ok(());
+                        }
+                        accepted = listener.accept() => accepted,
+                    };
+
+                    let (transport, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!(service, error = %e, "failed to accept a connection");
+                            continue;
+                        }
+                    };
+
+                    let permit = match limiter.acquire(peer.ip()) {
+                        Ok(permit) => permit,
+                        Err(rejection) => {
+                            let rejected = stats.count_rejected();
+                            warn!(service, %peer, %rejection, rejected, "rejecting connection");
+                            continue;
+                        }
+                    };
+
+                    let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+                    let span = info_span!("conn", service, id, %peer);
+                    let context = Context {
+                        id,
+                        peer,
+                        shutdown: shutdown.clone(),
+                        timeouts: timeouts.clone(),
+                        metrics: metrics.clone(),
+                    };
+
+                    metrics.connections.inc();
+                    metrics.open.inc();
+                    let open = Arc::clone(&metrics.open);
+
+                    let recording = match (&recorder, peer) {
+                        (Some(recorder), Peer::Ip(addr)) => {
+                            Some(Recording::new(recorder, service, id, addr))
+                        }
+                        _ => None,
+                    };
+                    if let Some(recording) = &recording {
+                        recording.record(EventKind::Open, &[]);
+                    }
+
+                    let registered = admin::registry().open(service, id, peer);
+                    let handler = Arc::clone(&handler);
+                    let tls = tls.clone();
+                    let metrics = metrics.clone();
+                    let session = async move {
+                        info!("connected");
+                        let served = async {
+                            match handshake(transport, tls.as_ref(), context.timeouts()).await {
+                                Ok(transport) => {
+                                    let stream =
+                                        Stream::new(transport, &metrics, recording.clone());
+                                    match handler(stream, context).await {
+                                        Ok(()) => info!("disconnected"),
+                                        Err(e) => warn!(error = %e, "dropping client"),
+                                    }
+                                }
+                                Err(e) => warn!(error = %e, "TLS handshake failed"),
+                            }
+                        };
+                        // A kicked handler is dropped where it stands, closing
+                        // the connection
+                        tokio::select! {
+                            _ = registered.kicked() => info!("kicked"),
+                            _ = served => {}
+                        }
+                        drop(registered);
+                        if let Some(recording) = recording {
+                            recording.record(EventKind::Close, &[]);
+                        }
+                        open.dec();
+                        drop(permit);
+                    };
+                    shutdown.spawn(session.instrument(span));
+                }
+            });
+        }
+
+        join_all(tasks).await
+    }
+
+    /// Receive datagrams of up to `max_size` bytes on every socket until the
+    /// shutdown is triggered. The handler returns the reply to send back to the
+    /// sender, if any. A bad datagram is logged and skipped.
+    pub async fn serve_udp<H, E>(
+        &self,
+        sockets: Vec<std::net::UdpSocket>,
+        max_size: usize,
+        handler: H,
+    ) -> std::io::Result<()>
+    where
+        H: Fn(&[u8], SocketAddr) -> Result<Option<Vec<u8>>, E> + Send + Sync + 'static,
+        E: Display,
+    {
+        let handler = Arc::new(handler);
+        let mut tasks = JoinSet::new();
+
+        if self.tls.is_some() && !sockets.is_empty() {
+            warn!(
+                service = self.name,
+                "TLS is only spoken over TCP, serving datagrams in plaintext"
+            );
+        }
+
+        for socket in sockets {
+            socket.set_nonblocking(true)?;
+            let socket = UdpSocket::from_std(socket)?;
+            info!(service = self.name, addr = %socket.local_addr()?, "listening");
+            let handler = Arc::clone(&handler);
+            let shutdown = self.shutdown.clone();
+            let metrics = self.metrics.clone();
+            let recorder = self.recorder.clone();
+            let service = self.name;
+            let span = info_span!("udp", service);
+
+            let receive = async move {
+                let mut recv_buff: Vec<u8> = vec![0; max_size];
+
+                loop {
+                    let received = tokio::select! {
+                        _ = shutdown.triggered() => return Ok(()),
+                        received = socket.recv_from(&mut recv_buff) => received,
+                    };
+
+                    let (sz, addr) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            warn!(error = %e, "failed to receive a datagram");
+                            continue;
+                        }
+                    };
+
+                    debug!(peer = %addr, size = sz, "received a datagram");
+                    metrics.datagrams.inc();
+                    metrics.received.add(sz as u64);
+                    if let Some(recorder) = &recorder {
+                        recorder.datagram(service, addr, EventKind::In, &recv_buff[..sz]);
+                    }
+
+                    let timer = metrics.requests.start_timer();
+                    let reply = match handler(&recv_buff[..sz], addr) {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            warn!(peer = %addr, error = %e, "ignoring datagram");
+                            continue;
+                        }
+                    };
+
+                    if let Some(reply) = reply {
+                        match socket.send_to(&reply, addr).await {
+                            Ok(n) => {
+                                metrics.sent.add(n as u64);
+                                if let Some(recorder) = &recorder {
+                                    recorder.datagram(service, addr, EventKind::Out, &reply);
+                                }
+                            }
+                            Err(e) => warn!(peer = %addr, error = %e, "failed to reply"),
+                        }
+                    }
+                    drop(timer);
+                }
+            };
+            tasks.spawn(receive.instrument(span));
+        }
+
+        join_all(tasks).await
+    }
+
+    /// Receive datagrams on every Unix datagram socket, as
+    /// [`Server::serve_udp`] does. Replies only reach senders bound to a path
+    /// of their own, and the traffic is not recorded. The socket files are
+    /// removed once the shutdown is triggered.
+    pub async fn serve_unix_datagram<H, E>(
+        &self,
+        sockets: Vec<std::os::unix::net::UnixDatagram>,
+        max_size: usize,
+        handler: H,
+    ) -> std::io::Result<()>
+    where
+        H: Fn(&[u8]) -> Result<Option<Vec<u8>>, E> + Send + Sync + 'static,
+        E: Display,
+    {
+        let handler = Arc::new(handler);
+        let mut tasks = JoinSet::new();
+
+        for socket in sockets {
+            socket.set_nonblocking(true)?;
+            let socket = UnixDatagram::from_std(socket)?;
+            let path = socket
+                .local_addr()?
+                .as_pathname()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            info!(service = self.name, addr = %format!("unixgram://{}", path.display()), "listening");
+            let handler = Arc::clone(&handler);
+            let shutdown = self.shutdown.clone();
+            let metrics = self.metrics.clone();
+            let span = info_span!("unixgram", service = self.name);
+
+            let receive = async move {
+                let mut recv_buff: Vec<u8> = vec![0; max_size];
+
+                loop {
+                    let received = tokio::select! {
+                        _ = shutdown.triggered() => {
+                            drop(socket);
+                            let _ = std::fs::remove_file(path);
+                            return Ok(());
+                        }
+                        received = socket.recv_from(&mut recv_buff) => received,
+                    };
+
+                    let (sz, addr) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            warn!(error = %e, "failed to receive a datagram");
+                            continue;
+                        }
+                    };
+
+                    debug!(size = sz, "received a datagram");
+                    metrics.datagrams.inc();
+                    metrics.received.add(sz as u64);
+
+                    let timer = metrics.requests.start_timer();
+                    let reply = match handler(&recv_buff[..sz]) {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            warn!(error = %e, "ignoring datagram");
+                            continue;
+                        }
+                    };
+
+                    match (reply, addr.as_pathname()) {
+                        (Some(reply), Some(sender)) => match socket.send_to(&reply, sender).await {
+                            Ok(n) => metrics.sent.add(n as u64),
+                            Err(e) => warn!(error = %e, "failed to reply"),
+                        },
+                        (Some(_), None) => debug!("cannot reply to a sender without a path"),
+                        (None, _) => {}
+                    }
+                    drop(timer);
+                }
+            };
+            tasks.spawn(receive.instrument(span));
+        }
+
+        join_all(tasks).await
+    }
+
+    /// Serve a request/response protocol on every listener, as
+    /// [`Server::serve_tcp`] does for a handler.
+    pub async fn serve_protocol<P: Protocol>(
+        &self,
+        listeners: Vec<std::net::TcpListener>,
+        protocol: P,
+    ) -> std::io::Result<()> {
+        let protocol = Arc::new(protocol);
+
+        self.serve_tcp(listeners, move |stream, context| {
+            let protocol = Arc::clone(&protocol);
+            async move { run_session(protocol.as_ref(), stream, &context).await }
+        })
+        .await
+    }
+
+    /// Serve a datagram protocol on every socket, as [`Server::serve_udp`]
+    /// does for a handler. Each datagram is handled with a fresh session.
+    pub async fn serve_datagram_protocol<P>(
+        &self,
+        sockets: Vec<std::net::UdpSocket>,
+        protocol: P,
+    ) -> std::io::Result<()>
+    where
+        P: Protocol<Codec = DatagramCodec>,
+    {
+        let max_size = protocol.codec().max_size();
+
+        self.serve_udp(sockets, max_size, move |datagram, _| {
+            handle_datagram(&protocol, datagram)
+        })
+        .await
+    }
+}
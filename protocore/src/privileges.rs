@@ -0,0 +1,77 @@
+//! Dropping root once the sockets are bound, so a server can take port 80
+//! and then serve as an unprivileged user.
+
+use nix::unistd::{self, Gid, Group, Uid, User};
+use std::io::{Error, ErrorKind};
+use tracing::info;
+
+fn lookup_error(what: &str, name: &str, e: nix::Error) -> Error {
+    Error::other(format!("cannot look up {what} `{name}`: {e}"))
+}
+
+fn user(name: &str) -> std::io::Result<User> {
+    match User::from_name(name) {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(Error::new(ErrorKind::NotFound, format!("no user `{name}`"))),
+        Err(e) => Err(lookup_error("user", name, e)),
+    }
+}
+
+fn group(name: &str) -> std::io::Result<Gid> {
+    match Group::from_name(name) {
+        Ok(Some(group)) => Ok(group.gid),
+        Ok(None) => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no group `{name}`"),
+        )),
+        Err(e) => Err(lookup_error("group", name, e)),
+    }
+}
+
+fn switch_error(what: &str, e: nix::Error) -> Error {
+    Error::new(
+        ErrorKind::PermissionDenied,
+        format!("cannot switch {what}: {e}"),
+    )
+}
+
+/// Switch the process to `user` and `group`, by name. The group defaults to
+/// the primary group of the user; with neither, nothing changes.
+///
+/// The supplementary groups are dropped along with root, and switching back
+/// is checked to fail.
+pub fn drop_to(user_name: Option<&str>, group_name: Option<&str>) -> std::io::Result<()> {
+    let user = user_name.map(user).transpose()?;
+    let gid = match (group_name, &user) {
+        (Some(name), _) => Some(group(name)?),
+        (None, Some(user)) => Some(user.gid),
+        (None, None) => None,
+    };
+
+    if let Some(gid) = gid {
+        unistd::setgroups(&[gid]).map_err(|e| switch_error("the groups", e))?;
+        unistd::setgid(gid).map_err(|e| switch_error("group", e))?;
+    }
+    if let Some(user) = &user {
+        unistd::setuid(user.uid).map_err(|e| switch_error("user", e))?;
+
+        if !user.uid.is_root() && unistd::setuid(Uid::from_raw(0)).is_ok() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "root could be regained after switching user",
+            ));
+        }
+    }
+
+    if gid.is_some() {
+        info!(
+            user = user_name.unwrap_or_default(),
+            group = group_name.unwrap_or_default(),
+            uid = unistd::getuid().as_raw(),
+            gid = unistd::getgid().as_raw(),
+            "dropped privileges"
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! Request/response protocols, written as a handler of decoded messages.
+//!
+//! A [`Protocol`] picks a [`Codec`] and answers each message; the session
+//! loop around it reads, enforces the size limits and deadlines, frames the
+//! replies and waits for them to be sent before reading the next message, so
+//! a client that does not read its replies stops being read from.
+
+use std::fmt;
+use std::fmt::Display;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::codec::{Codec, DatagramCodec, Framed};
+use crate::io::{send_to_socket, ReadError};
+use crate::net::Context;
+
+/// What to do with the connection once a message is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// Close the connection once the pending replies are sent.
+    Close,
+}
+
+/// Replies to send back to the client, in order, before the next message is
+/// read. They are framed by the codec of the protocol.
+#[derive(Default)]
+pub struct Replies {
+    pending: Vec<Vec<u8>>,
+}
+
+impl Replies {
+    pub fn send(&mut self, reply: impl Into<Vec<u8>>) {
+        self.pending.push(reply.into());
+    }
+
+    /// Frame every pending reply into a single buffer.
+    fn encode<C: Codec>(&mut self, codec: &C) -> Vec<u8> {
+        let mut out = Vec::new();
+        for reply in self.pending.drain(..) {
+            codec.encode(&reply, &mut out);
+        }
+        out
+    }
+}
+
+/// Why a session ended early.
+#[derive(Debug)]
+pub enum SessionError<E> {
+    Read(ReadError),
+    Write(std::io::Error),
+    /// The protocol rejected the client.
+    Protocol(E),
+}
+
+impl<E: Display> fmt::Display for SessionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Read(e) => write!(f, "{e}"),
+            SessionError::Write(e) => write!(f, "failed to send back to the client: {e}"),
+            SessionError::Protocol(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + Display> std::error::Error for SessionError<E> {}
+
+/// A request/response protocol, served with [`crate::net::Server::serve_protocol`]
+/// over TCP, or [`crate::net::Server::serve_datagram_protocol`] over UDP.
+///
+/// The protocol itself is shared by every connection, for state such as a
+/// database; the per-connection state lives in its `Session`.
+///
+/// A protocol only ever answers the message it was given, and on its own
+/// connection. Services that write to a client unprompted stay on
+/// [`crate::net::Server::serve_tcp`]: the chat relays the messages of the
+/// other users, and the proxy forwards whatever the upstream sends.
+pub trait Protocol: Send + Sync + 'static {
+    type Codec: Codec;
+    type Session: Default + Send;
+    type Error: Display + Send;
+
+    /// The codec of a new connection.
+    fn codec(&self) -> Self::Codec;
+
+    /// Called once a client connects, before any message is read.
+    fn on_connect(
+        &self,
+        _session: &mut Self::Session,
+        _replies: &mut Replies,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a message. Over a connection, replies sent before an error
+    /// still reach the client before it is dropped; over UDP, an error drops
+    /// the datagram along with its replies.
+    fn on_message(
+        &self,
+        session: &mut Self::Session,
+        message: <Self::Codec as Codec>::Message,
+        replies: &mut Replies,
+    ) -> Result<Flow, Self::Error>;
+}
+
+/// Send the pending replies, framed.
+async fn flush<W: AsyncWrite + Unpin, C: Codec>(
+    stream: &mut W,
+    codec: &C,
+    replies: &mut Replies,
+) -> Result<(), std::io::Error> {
+    if replies.pending.is_empty() {
+        return Ok(());
+    }
+
+    send_to_socket(stream, &replies.encode(codec)).await
+}
+
+/// Run a protocol on a connection until the client leaves, the protocol
+/// closes it, or either fails.
+pub(crate) async fn run_session<P, S>(
+    protocol: &P,
+    stream: S,
+    context: &Context,
+) -> Result<(), SessionError<P::Error>>
+where
+    P: Protocol,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut framed =
+        Framed::new(stream, protocol.codec()).with_timeouts(context.timeouts().clone());
+    let mut session = P::Session::default();
+    let mut replies = Replies::default();
+
+    let connected = protocol.on_connect(&mut session, &mut replies);
+    let (stream, codec) = framed.parts_mut();
+    flush(stream, codec, &mut replies)
+        .await
+        .map_err(SessionError::Write)?;
+    connected.map_err(SessionError::Protocol)?;
+
+    loop {
+        let message = match framed.read_message().await {
+            Ok(message) => message,
+            Err(ReadError::Eof) => return Ok(()),
+            Err(e) => return Err(SessionError::Read(e)),
+        };
+
+        let _request = context.start_request();
+        let flow = protocol.on_message(&mut session, message, &mut replies);
+        let (stream, codec) = framed.parts_mut();
+        flush(stream, codec, &mut replies)
+            .await
+            .map_err(SessionError::Write)?;
+
+        if flow.map_err(SessionError::Protocol)? == Flow::Close {
+            return Ok(());
+        }
+    }
+}
+
+/// Handle a single datagram with a fresh session. A datagram is a message,
+/// even an empty one; every reply is sent back in one datagram, and `None`
+/// means no reply. On an error nothing is sent back, the replies included.
+pub(crate) fn handle_datagram<P>(protocol: &P, datagram: &[u8]) -> Result<Option<Vec<u8>>, P::Error>
+where
+    P: Protocol<Codec = DatagramCodec>,
+{
+    let codec = protocol.codec();
+    let mut session = P::Session::default();
+    let mut replies = Replies::default();
+
+    protocol.on_connect(&mut session, &mut replies)?;
+
+    let message = datagram[..datagram.len().min(codec.max_size())].to_vec();
+    protocol.on_message(&mut session, message, &mut replies)?;
+
+    if replies.pending.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(replies.encode(&codec)))
+}
@@ -0,0 +1,183 @@
+//! Opt-in recording of the bytes exchanged with clients, so a failed checker
+//! run can be replayed against a server afterwards.
+//!
+//! A recording is a file of JSON lines, one [`Event`] per line, in the order
+//! they happened across every connection of the process.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tracing::warn;
+
+/// What happened on a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A client connected.
+    Open,
+    /// Bytes, or a datagram, received from the client.
+    In,
+    /// Bytes, or a datagram, sent to the client.
+    Out,
+    /// The connection was closed.
+    Close,
+}
+
+/// A line of a recording.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Microseconds since the recording started.
+    pub at: u64,
+    pub service: String,
+    /// Id of the connection, as found in the logs. Datagrams have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conn: Option<u64>,
+    pub peer: SocketAddr,
+    pub kind: EventKind,
+    /// The bytes exchanged, hex encoded.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+}
+
+impl Event {
+    /// The bytes exchanged, decoded.
+    pub fn bytes(&self) -> Result<Vec<u8>, String> {
+        from_hex(&self.data)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in `{s}`"));
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("`{s}` is not hex encoded"))
+        })
+        .collect()
+}
+
+fn write_event(file: &mut impl Write, event: &Event) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *file, event)?;
+    file.write_all(b"\n")
+}
+
+/// Writes events to a recording file.
+///
+/// Events are handed to a dedicated thread, so recording never blocks the
+/// runtime on file I/O.
+pub struct Recorder {
+    started: Instant,
+    events: Sender<Event>,
+}
+
+impl Recorder {
+    /// Start recording to `path`, truncating it.
+    pub fn create(path: &Path) -> std::io::Result<Arc<Recorder>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (events, rx) = channel::<Event>();
+
+        thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || {
+                while let Ok(event) = rx.recv() {
+                    // Flush whenever we catch up, so the file is usable while
+                    // the server is still running
+                    let result = std::iter::once(event)
+                        .chain(rx.try_iter())
+                        .try_for_each(|event| write_event(&mut file, &event))
+                        .and_then(|_| file.flush());
+
+                    if let Err(e) = result {
+                        warn!(error = %e, "failed to write to the recording, stopping it");
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(Arc::new(Recorder {
+            started: Instant::now(),
+            events,
+        }))
+    }
+
+    fn record(
+        &self,
+        service: &str,
+        conn: Option<u64>,
+        peer: SocketAddr,
+        kind: EventKind,
+        data: &[u8],
+    ) {
+        let event = Event {
+            at: self.started.elapsed().as_micros() as u64,
+            service: service.to_string(),
+            conn,
+            peer,
+            kind,
+            data: to_hex(data),
+        };
+
+        // The writer only goes away after a write error, already reported
+        let _ = self.events.send(event);
+    }
+
+    /// Record a datagram received from or sent to `peer`.
+    pub fn datagram(&self, service: &str, peer: SocketAddr, kind: EventKind, data: &[u8]) {
+        self.record(service, None, peer, kind, data);
+    }
+}
+
+/// The recording of a single connection.
+#[derive(Clone)]
+pub struct Recording {
+    recorder: Arc<Recorder>,
+    service: &'static str,
+    conn: u64,
+    peer: SocketAddr,
+}
+
+impl Recording {
+    pub(crate) fn new(
+        recorder: &Arc<Recorder>,
+        service: &'static str,
+        conn: u64,
+        peer: SocketAddr,
+    ) -> Recording {
+        Recording {
+            recorder: Arc::clone(recorder),
+            service,
+            conn,
+            peer,
+        }
+    }
+
+    pub fn record(&self, kind: EventKind, data: &[u8]) {
+        self.recorder
+            .record(self.service, Some(self.conn), self.peer, kind, data);
+    }
+}
+
+/// Read back every event of a recording.
+pub fn read_events(path: &Path) -> std::io::Result<Vec<Event>> {
+    let file = BufReader::new(File::open(path)?);
+
+    file.lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
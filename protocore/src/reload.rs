@@ -0,0 +1,52 @@
+//! Settings replaced while the servers run, e.g. when the config file is
+//! reloaded.
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// A value shared by the servers and whoever reloads it.
+///
+/// Readers get the value current at the time they ask, so connections opened
+/// after [`Reloadable::set`] see the new value while ongoing ones can keep the
+/// one they started with.
+pub struct Reloadable<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Reloadable<T> {
+        Reloadable(Arc::new(RwLock::new(Arc::new(value))))
+    }
+
+    /// The current value.
+    pub fn get(&self) -> Arc<T> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+
+    /// Replace the value for every reader from now on.
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Reloadable(Arc::clone(&self.0))
+    }
+}
+
+impl<T: Default> Default for Reloadable<T> {
+    fn default() -> Self {
+        Reloadable::new(T::default())
+    }
+}
+
+impl<T> From<T> for Reloadable<T> {
+    fn from(value: T) -> Self {
+        Reloadable::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Reloadable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
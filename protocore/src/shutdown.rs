@@ -0,0 +1,87 @@
+//! Graceful shutdown: stop accepting, let handlers say goodbye, then drain the
+//! remaining connections for a bounded time.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+/// Shared by the accept loops and every connection of a process.
+///
+/// Triggering it stops the accept loops and wakes up handlers waiting on
+/// [`Shutdown::triggered`]. Connection tasks are tracked so [`Shutdown::drain`]
+/// can wait for them.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// A handle triggered on SIGINT or SIGTERM.
+    pub fn on_signal() -> std::io::Result<Shutdown> {
+        let shutdown = Shutdown::new();
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+
+        let token = shutdown.token.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = terminate.recv() => info!("received SIGTERM, shutting down"),
+                _ = interrupt.recv() => info!("received SIGINT, shutting down"),
+            }
+            token.cancel();
+        });
+
+        Ok(shutdown)
+    }
+
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once the shutdown is triggered.
+    pub async fn triggered(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Spawn a task that `drain` waits for.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tracker.spawn(task);
+    }
+
+    /// Wait up to `grace` for the tracked tasks to finish. Returns how many
+    /// were still running when the grace period ran out.
+    pub async fn drain(&self, grace: Duration) -> usize {
+        self.tracker.close();
+
+        if tokio::time::timeout(grace, self.tracker.wait())
+            .await
+            .is_ok()
+        {
+            return 0;
+        }
+
+        let remaining = self.tracker.len();
+        warn!(
+            remaining,
+            ?grace,
+            "connections still open after the grace period, closing them"
+        );
+        remaining
+    }
+}
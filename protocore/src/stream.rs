@@ -0,0 +1,220 @@
+//! The client side of a connection, as handed to the handlers.
+
+use std::io::IoSlice;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, UnixStream};
+use tokio_rustls::server::TlsStream;
+
+use crate::metrics::{Counter, ServiceMetrics};
+use crate::record::{EventKind, Recording};
+
+/// What a client connection runs over.
+pub(crate) enum Transport {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Unix(UnixStream),
+}
+
+/// Forward a poll to the stream of either transport.
+macro_rules! poll_transport {
+    ($transport:expr, $stream:ident => $poll:expr) => {
+        match $transport {
+            Transport::Plain($stream) => $poll,
+            Transport::Tls($stream) => $poll,
+            Transport::Unix($stream) => $poll,
+        }
+    };
+}
+
+/// A client connection, counting the bytes that go through it and recording
+/// them when the server has a recorder. Over TLS, what is counted and recorded
+/// is the plaintext.
+pub struct Stream {
+    inner: Transport,
+    received: Arc<Counter>,
+    sent: Arc<Counter>,
+    recording: Option<Recording>,
+}
+
+impl Stream {
+    pub(crate) fn new(
+        inner: Transport,
+        metrics: &ServiceMetrics,
+        recording: Option<Recording>,
+    ) -> Stream {
+        Stream {
+            inner,
+            received: Arc::clone(&metrics.received),
+            sent: Arc::clone(&metrics.sent),
+            recording,
+        }
+    }
+
+    fn record(&self, kind: EventKind, data: &[u8]) {
+        if let Some(recording) = &self.recording {
+            recording.record(kind, data);
+        }
+    }
+
+    /// The address of the client, an error over a Unix socket.
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        match &self.inner {
+            Transport::Plain(stream) => stream.peer_addr(),
+            Transport::Tls(stream) => stream.get_ref().0.peer_addr(),
+            Transport::Unix(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix clients have no IP address",
+            )),
+        }
+    }
+
+    /// Send small writes right away rather than waiting to fill a packet.
+    /// Unix sockets always do.
+    pub fn set_nodelay(&self, nodelay: bool) -> std::io::Result<()> {
+        match &self.inner {
+            Transport::Plain(stream) => stream.set_nodelay(nodelay),
+            Transport::Tls(stream) => stream.get_ref().0.set_nodelay(nodelay),
+            Transport::Unix(_) => Ok(()),
+        }
+    }
+
+    /// Whether the client is connected over TLS.
+    pub fn is_tls(&self) -> bool {
+        matches!(self.inner, Transport::Tls(_))
+    }
+
+    /// The TCP socket under the stream, for handlers moving bytes without
+    /// reading them, e.g. with `splice`. `None` over TLS or a Unix socket,
+    /// and when the traffic is recorded, as those bytes have to go through
+    /// the stream.
+    ///
+    /// Bytes moved this way are not counted, report them with
+    /// [`Stream::count_moved`].
+    pub fn as_plain_tcp(&self) -> Option<&TcpStream> {
+        match &self.inner {
+            Transport::Plain(stream) if self.recording.is_none() => Some(stream),
+            _ => None,
+        }
+    }
+
+    /// Count bytes moved around the stream, straight on its socket.
+    pub fn count_moved(&self, received: u64, sent: u64) {
+        self.received.add(received);
+        self.sent.add(sent);
+    }
+
+    /// Split the stream into halves that can be used from separate tasks.
+    pub fn into_split(self) -> (ReadHalf<Stream>, WriteHalf<Stream>) {
+        tokio::io::split(self)
+    }
+}
+
+/// Writes as much as a TCP stream takes without waiting.
+struct TryWriter<'a>(&'a TcpStream);
+
+impl std::io::Write for TryWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.try_write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Handlers simply drop their stream once done. Over TLS, tell the client the
+/// session ended on purpose rather than being cut, as well as the socket
+/// allows without waiting.
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if let Transport::Tls(stream) = &mut self.inner {
+            let (tcp, connection) = stream.get_mut();
+            connection.send_close_notify();
+
+            while connection.wants_write() {
+                match connection.write_tls(&mut TryWriter(tcp)) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = poll_transport!(&mut self.inner, s => Pin::new(s).poll_read(cx, buf));
+
+        if let Poll::Ready(Ok(())) = polled {
+            let received = &buf.filled()[before..];
+            self.received.add(received.len() as u64);
+            if !received.is_empty() {
+                self.record(EventKind::In, received);
+            }
+        }
+
+        polled
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let polled = poll_transport!(&mut self.inner, s => Pin::new(s).poll_write(cx, buf));
+
+        if let Poll::Ready(Ok(n)) = polled {
+            self.sent.add(n as u64);
+            self.record(EventKind::Out, &buf[..n]);
+        }
+
+        polled
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let polled =
+            poll_transport!(&mut self.inner, s => Pin::new(s).poll_write_vectored(cx, bufs));
+
+        if let Poll::Ready(Ok(n)) = polled {
+            self.sent.add(n as u64);
+            if self.recording.is_some() {
+                let written: Vec<u8> = bufs
+                    .iter()
+                    .flat_map(|b| b.iter())
+                    .copied()
+                    .take(n)
+                    .collect();
+                self.record(EventKind::Out, &written);
+            }
+        }
+
+        polled
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        poll_transport!(&self.inner, s => s.is_write_vectored())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        poll_transport!(&mut self.inner, s => Pin::new(s).poll_flush(cx))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        poll_transport!(&mut self.inner, s => Pin::new(s).poll_shutdown(cx))
+    }
+}
@@ -0,0 +1,115 @@
+//! Optional TLS, terminated by the TCP servers or spoken to an upstream.
+//!
+//! Certificates and keys are read from PEM files. Only the async servers
+//! speak TLS.
+
+use clap::Args;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Serve TLS instead of plaintext.
+#[derive(Args, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsArgs {
+    /// Serve TLS with this certificate chain, in PEM. Needs --tls-key
+    #[arg(
+        long,
+        env = "PROTOHACKER_TLS_CERT",
+        value_name = "FILE",
+        requires = "tls_key"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key of --tls-cert, in PEM
+    #[arg(
+        long,
+        env = "PROTOHACKER_TLS_KEY",
+        value_name = "FILE",
+        requires = "tls_cert"
+    )]
+    pub tls_key: Option<PathBuf>,
+}
+
+impl TlsArgs {
+    pub fn is_enabled(&self) -> bool {
+        self.tls_cert.is_some()
+    }
+
+    /// The acceptor asked for with `--tls-cert` and `--tls-key`, if any.
+    pub fn acceptor(&self) -> std::io::Result<Option<TlsAcceptor>> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => acceptor(cert, key).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Tell which file a PEM error came from.
+fn invalid(path: &Path, e: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("{}: {e}", path.display()))
+}
+
+fn read_certs(path: &Path) -> std::io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(path, e))?;
+
+    if certs.is_empty() {
+        return Err(invalid(path, "no certificate found"));
+    }
+
+    Ok(certs)
+}
+
+/// Accept TLS connections with the certificate chain at `cert` and the
+/// private key at `key`.
+pub fn acceptor(cert: &Path, key: &Path) -> std::io::Result<TlsAcceptor> {
+    let certs = read_certs(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, e))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| invalid(cert, e))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Open TLS connections trusting the certificates at `ca`, e.g. to reach a
+/// server with a self-signed certificate, or the usual web authorities.
+pub fn connector(ca: Option<&Path>) -> std::io::Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+
+    match ca {
+        Some(ca) => {
+            for cert in read_certs(ca)? {
+                roots.add(cert).map_err(|e| invalid(ca, e))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::other(e.to_string()))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// The name to check the certificate of `host` against: a DNS name or an IP.
+pub fn server_name(host: &str) -> std::io::Result<ServerName<'static>> {
+    ServerName::try_from(host.to_string()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("`{host}` is not a valid TLS server name: {e}"),
+        )
+    })
+}
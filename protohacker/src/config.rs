@@ -0,0 +1,499 @@
+//! The services to run and their settings, read from a TOML file.
+//!
+//! Each service is a table of `services`, named after it, with the port it
+//! listens on, the same listen, limit and TLS settings as the flags, and the
+//! settings of its protocol:
+//!
+//! ```toml
+//! [services.chat]
+//! port = 10003
+//! max_clients = 100
+//! idle_timeout = "5m"
+//! welcome = "Hi! What's your name?"
+//!
+//! [services.echo]
+//! port = 10000
+//! delay = "50ms"
+//! # A table in place of an address sets the diagnostics of the echo on it
+//! listen = ["0.0.0.0", { addr = "udp://0.0.0.0:10007", transform = "uppercase" }]
+//!
+//! [services.mitm]
+//! port = 10005
+//! upstream = "127.0.0.1:10003"
+//! ```
+//!
+//! The file is checked as a whole before anything is bound, and flags given
+//! on the command line or in the environment override it.
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use protocore::cli::{parse_duration, Family, ListenAddr, ListenArgs, ServerArgs};
+use protocore::limits::Limits;
+use protocore::tls::TlsArgs;
+use protohacker0::Diagnostics;
+use protohacker5::Upstream;
+use regex::Regex;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::service::{Service, Settings};
+
+/// Largest echo buffer, to keep a typo from taking the memory of the host.
+const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest payload of a UDP datagram.
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Why a config file was refused.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    /// Not TOML, or keys and values we do not know.
+    Parse(PathBuf, toml::de::Error),
+    /// Settings that do not make sense for the service.
+    Invalid(PathBuf, Service, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "{}: {e}", path.display()),
+            ConfigError::Invalid(path, service, message) => write!(
+                f,
+                "{}: [services.{}] {message}",
+                path.display(),
+                service.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// A value written as a string and parsed the way its flag is, e.g. an
+/// upstream or a listen address.
+struct Parsed<T>(T);
+
+impl<'de, T> Deserialize<'de> for Parsed<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map(Parsed).map_err(de::Error::custom)
+    }
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_duration(&s).map(Some).map_err(de::Error::custom)
+}
+
+fn family<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Family>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    Family::from_str(&s, true).map(Some).map_err(|_| {
+        de::Error::custom(format!("unknown family `{s}`, expected ipv4, ipv6 or dual"))
+    })
+}
+
+fn transform<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<protohacker0::Transform>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    protohacker0::Transform::from_str(&s, true)
+        .map(Some)
+        .map_err(|_| {
+            de::Error::custom(format!(
+                "unknown transform `{s}`, expected none, uppercase, reverse-line or hex-dump"
+            ))
+        })
+}
+
+/// A listen address of a service, as written in the file: the address alone,
+/// or a table with the address and diagnostics of the echo on it, which take
+/// over those of the service.
+struct Listen {
+    addr: ListenAddr,
+    diagnostics: Option<Diagnostics>,
+}
+
+/// A listen address written as a table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListenTable {
+    addr: Parsed<ListenAddr>,
+    #[serde(default, deserialize_with = "duration")]
+    delay: Option<Duration>,
+    throttle: Option<u64>,
+    fragment: Option<usize>,
+    #[serde(default, deserialize_with = "transform")]
+    transform: Option<protohacker0::Transform>,
+}
+
+impl<'de> Deserialize<'de> for Listen {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Listen;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a listen address, or a table with its `addr`")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Listen, E> {
+                let addr = s.parse().map_err(E::custom)?;
+                Ok(Listen {
+                    addr,
+                    diagnostics: None,
+                })
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Listen, A::Error> {
+                let table = ListenTable::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(Listen {
+                    addr: table.addr.0,
+                    diagnostics: Some(Diagnostics {
+                        delay: table.delay,
+                        throttle: table.throttle,
+                        fragment: table.fragment,
+                        transform: table.transform,
+                    }),
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Refuse a throttle of zero, and fragments of zero or above the buffer size
+/// cap.
+fn check_shaping(throttle: Option<u64>, fragment: Option<usize>) -> Result<Option<usize>, String> {
+    if throttle == Some(0) {
+        return Err("`throttle` must be at least 1 byte per second".to_string());
+    }
+    check_size("fragment", fragment, MAX_BUFFER_SIZE)
+}
+
+/// A service, as written in the file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Table {
+    port: Option<u16>,
+    #[serde(default)]
+    listen: Vec<Listen>,
+    #[serde(default, deserialize_with = "family")]
+    family: Option<Family>,
+
+    max_clients: Option<usize>,
+    max_clients_per_ip: Option<usize>,
+    #[serde(default, deserialize_with = "duration")]
+    idle_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "duration")]
+    read_timeout: Option<Duration>,
+
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+
+    buffer_size: Option<usize>,
+    splice: Option<bool>,
+    #[serde(default, deserialize_with = "duration")]
+    delay: Option<Duration>,
+    throttle: Option<u64>,
+    fragment: Option<usize>,
+    #[serde(default, deserialize_with = "transform")]
+    transform: Option<protohacker0::Transform>,
+    max_request_size: Option<usize>,
+    max_line_size: Option<usize>,
+    welcome: Option<String>,
+    max_datagram_size: Option<usize>,
+    upstream: Option<Parsed<Upstream>>,
+    upstream_ca: Option<PathBuf>,
+    address: Option<String>,
+    pattern: Option<Parsed<Regex>>,
+    #[serde(default, deserialize_with = "duration")]
+    upstream_timeout: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    services: BTreeMap<Service, Table>,
+}
+
+/// The services of a config file, along with where they listen, and the
+/// settings of their protocols.
+#[derive(Default)]
+pub struct Config {
+    pub services: Vec<(Service, ServerArgs)>,
+    pub settings: Settings,
+}
+
+impl Config {
+    /// Read and check the config file at `path`. Relative paths in the file,
+    /// e.g. to certificates, are relative to the file itself.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        let file: File =
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut config = Config::default();
+        for (service, table) in file.services {
+            let args = table
+                .apply(service, dir, &mut config.settings)
+                .map_err(|message| ConfigError::Invalid(path.to_path_buf(), service, message))?;
+            config.services.push((service, args));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Refuse sizes of zero, or above `max`.
+fn check_size(key: &str, size: Option<usize>, max: usize) -> Result<Option<usize>, String> {
+    match size {
+        Some(size) if size == 0 || size > max => {
+            Err(format!("`{key}` must be between 1 and {max}, got {size}"))
+        }
+        _ => Ok(size),
+    }
+}
+
+impl Table {
+    /// Check the table against its service, filling in the settings of its
+    /// protocol and giving back where it listens.
+    fn apply(
+        self,
+        service: Service,
+        dir: &Path,
+        settings: &mut Settings,
+    ) -> Result<ServerArgs, String> {
+        use Service::*;
+
+        let port = self.port.ok_or("`port` is missing")?;
+
+        let owned: [(&str, bool, &[Service]); 15] = [
+            ("buffer_size", self.buffer_size.is_some(), &[Echo]),
+            ("splice", self.splice.is_some(), &[Echo]),
+            ("delay", self.delay.is_some(), &[Echo]),
+            ("throttle", self.throttle.is_some(), &[Echo]),
+            ("fragment", self.fragment.is_some(), &[Echo]),
+            ("transform", self.transform.is_some(), &[Echo]),
+            (
+                "max_request_size",
+                self.max_request_size.is_some(),
+                &[Prime],
+            ),
+            ("max_line_size", self.max_line_size.is_some(), &[Chat, Mitm]),
+            ("welcome", self.welcome.is_some(), &[Chat]),
+            ("max_datagram_size", self.max_datagram_size.is_some(), &[Kv]),
+            ("upstream", self.upstream.is_some(), &[Mitm]),
+            ("upstream_ca", self.upstream_ca.is_some(), &[Mitm]),
+            ("address", self.address.is_some(), &[Mitm]),
+            ("pattern", self.pattern.is_some(), &[Mitm]),
+            ("upstream_timeout", self.upstream_timeout.is_some(), &[Mitm]),
+        ];
+        for (key, set, services) in owned {
+            if set && !services.contains(&service) {
+                return Err(format!("`{key}` is not a setting of {}", service.name()));
+            }
+        }
+        let diagnosed = self.listen.iter().find(|listen| {
+            listen
+                .diagnostics
+                .as_ref()
+                .is_some_and(|diagnostics| *diagnostics != Diagnostics::default())
+        });
+        if let (Some(listen), false) = (diagnosed, service == Echo) {
+            return Err(format!(
+                "`listen` {} sets diagnostics of the echo, not of {}",
+                listen.addr,
+                service.name()
+            ));
+        }
+
+        let limits = Limits {
+            max_clients: self.max_clients,
+            max_clients_per_ip: self.max_clients_per_ip,
+            idle_timeout: self.idle_timeout,
+            read_timeout: self.read_timeout,
+        };
+        let tls = match (self.tls_cert, self.tls_key) {
+            (Some(_), None) => return Err("`tls_cert` needs `tls_key`".to_string()),
+            (None, Some(_)) => return Err("`tls_key` needs `tls_cert`".to_string()),
+            (cert, key) => TlsArgs {
+                tls_cert: cert.map(|cert| dir.join(cert)),
+                tls_key: key.map(|key| dir.join(key)),
+            },
+        };
+
+        if service == Kv {
+            let tcp_only = [
+                ("max_clients", limits.max_clients.is_some()),
+                ("max_clients_per_ip", limits.max_clients_per_ip.is_some()),
+                ("idle_timeout", limits.idle_timeout.is_some()),
+                ("read_timeout", limits.read_timeout.is_some()),
+                ("tls_cert", tls.is_enabled()),
+            ];
+            if let Some((key, _)) = tcp_only.iter().find(|(_, set)| *set) {
+                return Err(format!("`{key}` only applies to TCP services, kv is UDP"));
+            }
+        }
+
+        let max_line_size = check_size("max_line_size", self.max_line_size, usize::MAX)?;
+        match service {
+            Echo => {
+                let size = check_size("buffer_size", self.buffer_size, MAX_BUFFER_SIZE)?;
+                if let Some(size) = size {
+                    settings.echo.buffer_size = size;
+                }
+                if let Some(splice) = self.splice {
+                    settings.echo.splice = splice;
+                }
+                let fragment = check_shaping(self.throttle, self.fragment)?;
+                settings.echo.delay = self.delay;
+                settings.echo.throttle = self.throttle;
+                settings.echo.fragment = fragment;
+                settings.echo.transform = self.transform.unwrap_or_default();
+
+                settings.echo.listeners.clear();
+                for listen in &self.listen {
+                    if let Some(diagnostics) = &listen.diagnostics {
+                        check_shaping(diagnostics.throttle, diagnostics.fragment)
+                            .map_err(|e| format!("`listen` {}: {e}", listen.addr))?;
+                        settings
+                            .echo
+                            .listeners
+                            .push((listen.addr.clone(), diagnostics.clone()));
+                    }
+                }
+            }
+            Prime => {
+                let size = check_size("max_request_size", self.max_request_size, usize::MAX)?;
+                if let Some(size) = size {
+                    settings.prime.max_request_size = size;
+                }
+            }
+            Chat => {
+                if let Some(welcome) = self.welcome {
+                    if welcome.contains(['\n', '\r']) {
+                        return Err("`welcome` must be a single line".to_string());
+                    }
+                    settings.chat.welcome = welcome;
+                }
+                if let Some(size) = max_line_size {
+                    settings.chat.max_line_size = size;
+                }
+            }
+            Kv => {
+                let size =
+                    check_size("max_datagram_size", self.max_datagram_size, MAX_UDP_PAYLOAD)?;
+                if let Some(size) = size {
+                    settings.kv.max_datagram_size = size;
+                }
+            }
+            Mitm => {
+                let mitm = &mut settings.mitm;
+                if let Some(Parsed(upstream)) = self.upstream {
+                    mitm.upstream = upstream;
+                }
+                if let Some(ca) = self.upstream_ca {
+                    if !mitm.upstream.tls {
+                        return Err("`upstream_ca` needs a `tls://` upstream".to_string());
+                    }
+                    mitm.upstream.ca = Some(dir.join(ca));
+                }
+                if let Some(address) = self.address {
+                    if address.is_empty() || address.contains(char::is_whitespace) {
+                        return Err(format!("`address` must be a single word, got `{address}`"));
+                    }
+                    mitm.address = address;
+                }
+                if let Some(Parsed(pattern)) = self.pattern {
+                    mitm.pattern = pattern;
+                }
+                if let Some(size) = max_line_size {
+                    mitm.max_line_size = size;
+                }
+                if let Some(timeout) = self.upstream_timeout {
+                    mitm.upstream_timeout = timeout;
+                }
+            }
+            Means => {}
+        }
+
+        Ok(ServerArgs {
+            listen: ListenArgs {
+                listen: self.listen.into_iter().map(|listen| listen.addr).collect(),
+                port,
+                family: self.family.unwrap_or_default(),
+            },
+            limits,
+            tls,
+        })
+    }
+}
+
+/// The flags given on the command line or in the environment, rather than
+/// left to their defaults.
+#[derive(Clone)]
+pub struct Given(pub ArgMatches);
+
+impl Given {
+    pub fn contains(&self, id: &str) -> bool {
+        self.0
+            .value_source(id)
+            .is_some_and(|source| source != ValueSource::DefaultValue)
+    }
+}
+
+/// The arguments of a service from the config file, with the flags given
+/// overriding them. The port is left alone.
+pub fn override_args(file: ServerArgs, flags: ServerArgs, given: &Given) -> ServerArgs {
+    let listen = ListenArgs {
+        listen: if flags.listen.listen.is_empty() {
+            file.listen.listen
+        } else {
+            flags.listen.listen
+        },
+        port: file.listen.port,
+        family: if given.contains("family") {
+            flags.listen.family
+        } else {
+            file.listen.family
+        },
+    };
+
+    let limits = Limits {
+        max_clients: flags.limits.max_clients.or(file.limits.max_clients),
+        max_clients_per_ip: flags
+            .limits
+            .max_clients_per_ip
+            .or(file.limits.max_clients_per_ip),
+        idle_timeout: flags.limits.idle_timeout.or(file.limits.idle_timeout),
+        read_timeout: flags.limits.read_timeout.or(file.limits.read_timeout),
+    };
+
+    let tls = if flags.tls.is_enabled() {
+        flags.tls
+    } else {
+        file.tls
+    };
+
+    ServerArgs {
+        listen,
+        limits,
+        tls,
+    }
+}
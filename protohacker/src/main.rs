@@ -0,0 +1,379 @@
+mod config;
+mod reload;
+mod replay;
+mod service;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::{Config, ConfigError, Given};
+use protocore::activation::Activation;
+use protocore::cli::{RuntimeArgs, ServerArgs};
+use protocore::net::Server;
+use protocore::reload::Reloadable;
+use protocore::shutdown::Shutdown;
+use protocore::tls::TlsAcceptor;
+use protohacker5::Upstream;
+use reload::Reloader;
+use service::{Listener, LiveSettings, Service, ServiceSpec, Settings};
+use std::path::PathBuf;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+/// Run one or several protohackers servers from a single binary.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    runtime: RuntimeArgs,
+
+    /// Services to run and their settings, in TOML. Flags given on the command line or in
+    /// the environment override it
+    #[arg(long, global = true, env = "PROTOHACKER_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Chat server the mob in the middle proxies its clients to, as `tls://HOST:PORT` to
+    /// connect over TLS
+    #[arg(
+        long,
+        global = true,
+        env = "PROTOHACKER_UPSTREAM",
+        value_name = "HOST:PORT",
+        default_value_t
+    )]
+    upstream: Upstream,
+
+    /// Certificates to trust for a TLS upstream, in PEM, instead of the web authorities
+    #[arg(
+        long,
+        global = true,
+        env = "PROTOHACKER_UPSTREAM_CA",
+        value_name = "FILE"
+    )]
+    upstream_ca: Option<PathBuf>,
+
+    /// Use the thread-per-connection servers instead of the async ones
+    #[cfg(feature = "blocking")]
+    #[arg(long, global = true)]
+    blocking: bool,
+}
+
+#[derive(Clone, Subcommand)]
+enum Command {
+    /// Smoke test (protohacker0)
+    Echo {
+        #[command(flatten)]
+        server: ServerArgs,
+
+        #[command(flatten)]
+        diagnostics: protohacker0::Diagnostics,
+    },
+    /// Prime time (protohacker1)
+    Prime(ServerArgs),
+    /// Means to an end (protohacker2)
+    Means(ServerArgs),
+    /// Budget chat (protohacker3)
+    Chat(ServerArgs),
+    /// Unusual database program (protohacker4)
+    Kv(ServerArgs),
+    /// Mob in the middle (protohacker5)
+    Mitm(ServerArgs),
+    /// Replay a session recorded with `--record` against a server, diffing its replies
+    Replay(replay::ReplayArgs),
+    /// Run several servers at once, e.g. `run echo=10000 chat=10003`, or the services of
+    /// --config, with the ports given here overriding theirs
+    Run {
+        #[arg(value_name = "SERVICE=PORT")]
+        services: Vec<ServiceSpec>,
+
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+}
+
+impl Command {
+    /// Every service to run, along with where it listens: the ones of the
+    /// config file, overridden by the flags given.
+    fn into_services(
+        self,
+        configured: Vec<(Service, ServerArgs)>,
+        given: &Given,
+    ) -> Vec<(Service, ServerArgs)> {
+        let single = |service, args: ServerArgs, configured: Vec<(Service, ServerArgs)>| {
+            let port = args.listen.port;
+            let args = match configured.into_iter().find(|(s, _)| *s == service) {
+                Some((_, file)) => {
+                    let mut args = config::override_args(file, args, given);
+                    if given.contains("port") {
+                        args.listen.port = port;
+                    }
+                    args
+                }
+                None => args,
+            };
+            vec![(service, args)]
+        };
+
+        match self {
+            Command::Echo { server, .. } => single(Service::Echo, server, configured),
+            Command::Prime(args) => single(Service::Prime, args, configured),
+            Command::Means(args) => single(Service::Means, args, configured),
+            Command::Chat(args) => single(Service::Chat, args, configured),
+            Command::Kv(args) => single(Service::Kv, args, configured),
+            Command::Mitm(args) => single(Service::Mitm, args, configured),
+            Command::Run { services, server } => {
+                let mut planned: Vec<_> = configured
+                    .into_iter()
+                    .map(|(service, file)| {
+                        (service, config::override_args(file, server.clone(), given))
+                    })
+                    .collect();
+
+                // A port given for a service of the file moves it, any other
+                // is a service of its own
+                let mut moved = vec![false; planned.len()];
+                for spec in services {
+                    let configured = planned
+                        .iter()
+                        .zip(&moved)
+                        .position(|((service, _), moved)| *service == spec.service && !moved);
+
+                    match configured {
+                        Some(i) => {
+                            planned[i].1.listen.port = spec.port;
+                            moved[i] = true;
+                        }
+                        None => {
+                            let listen = server.listen.with_port(spec.port);
+                            let limits = server.limits.clone();
+                            let tls = server.tls.clone();
+                            planned.push((
+                                spec.service,
+                                ServerArgs {
+                                    listen,
+                                    limits,
+                                    tls,
+                                },
+                            ));
+                        }
+                    }
+                }
+
+                planned
+            }
+            Command::Replay(_) => unreachable!("replay does not run any service"),
+        }
+    }
+}
+
+/// Works out what to run from the flags and the config file, again on each
+/// reload.
+#[derive(Clone)]
+struct Planner {
+    command: Command,
+    given: Given,
+    config: Option<PathBuf>,
+    upstream: Upstream,
+    upstream_ca: Option<PathBuf>,
+}
+
+impl Planner {
+    fn plan(&self) -> Result<(Vec<(Service, ServerArgs)>, Settings), ConfigError> {
+        let config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        let mut settings = config.settings;
+        if self.given.contains("upstream") {
+            settings.mitm.upstream = Upstream {
+                ca: settings.mitm.upstream.ca,
+                ..self.upstream.clone()
+            };
+        }
+        if self.upstream_ca.is_some() {
+            settings.mitm.upstream.ca = self.upstream_ca.clone();
+        }
+        if let Command::Echo { diagnostics, .. } = &self.command {
+            settings.echo = settings.echo.with_diagnostics(diagnostics);
+        }
+
+        let services = self
+            .command
+            .clone()
+            .into_services(config.services, &self.given);
+        Ok((services, settings))
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    protocore::logging::init(cli.runtime.log_format);
+    // Taken before the runtime starts its threads, as it edits the environment
+    let mut activation = Activation::from_env();
+
+    #[cfg(feature = "blocking")]
+    let blocking = cli.blocking;
+    let runtime = cli.runtime;
+
+    let command = match cli.command {
+        Command::Replay(args) => return replay::run(args),
+        command => command,
+    };
+
+    let (_, matches) = matches.subcommand().expect("a subcommand is required");
+    let planner = Planner {
+        command,
+        given: Given(matches.clone()),
+        config: cli.config,
+        upstream: cli.upstream,
+        upstream_ca: cli.upstream_ca,
+    };
+    let (services, settings) = planner.plan().unwrap_or_else(|e| {
+        error!("invalid config, {e}");
+        std::process::exit(2);
+    });
+
+    if services.is_empty() {
+        error!("nothing to run, give `run` SERVICE=PORT pairs or a --config with services");
+        std::process::exit(2);
+    }
+
+    let mut claimed = Vec::new();
+    for (service, args) in &services {
+        for addr in args.listen.resolved(service.protocol()) {
+            if claimed.contains(&addr) {
+                error!(%addr, "the address is listened on more than once");
+                std::process::exit(2);
+            }
+            claimed.push(addr);
+        }
+    }
+
+    #[cfg(feature = "blocking")]
+    if blocking
+        && (settings.mitm.upstream.tls || services.iter().any(|(_, args)| args.tls.is_enabled()))
+    {
+        error!("TLS is only spoken by the async servers, drop --blocking to use it");
+        std::process::exit(2);
+    }
+
+    // Bind everything first so a busy port fails the whole run up front, and
+    // load the certificates so a bad one does too
+    let mut bound = Vec::with_capacity(services.len());
+    for (service, args) in services {
+        let listener = service.bind(&args.listen, &mut activation)?;
+        let tls = args.tls.acceptor()?;
+        bound.push((service, listener, args, tls));
+    }
+
+    #[cfg(feature = "blocking")]
+    if blocking {
+        if runtime.metrics.is_some() {
+            warn!("metrics are only served by the async servers, ignoring --metrics");
+        }
+        if runtime.admin.is_some() {
+            warn!("admin commands are only served by the async servers, ignoring --admin");
+        }
+        if runtime.record.is_some() {
+            warn!("sessions are only recorded by the async servers, ignoring --record");
+        }
+        for (service, _, args, _) in &bound {
+            if args.limits != protocore::limits::Limits::default() {
+                warn!(
+                    service = service.name(),
+                    "limits are only enforced by the async servers, ignoring them"
+                );
+            }
+        }
+        runtime.drop_privileges(&activation)?;
+        return serve_blocking(bound, settings);
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(serve(bound, runtime, activation, settings, planner))
+}
+
+async fn serve(
+    bound: Vec<(Service, Listener, ServerArgs, Option<TlsAcceptor>)>,
+    runtime: RuntimeArgs,
+    activation: Activation,
+    settings: Settings,
+    planner: Planner,
+) -> std::io::Result<()> {
+    let shutdown = Shutdown::on_signal()?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    if let Some(addr) = runtime.metrics {
+        protocore::metrics::spawn_endpoint(addr, &shutdown)?;
+    }
+    if let Some(path) = &runtime.admin {
+        protocore::admin::spawn_socket(path, &shutdown)?;
+    }
+
+    let recorder = runtime.recorder()?;
+    runtime.drop_privileges(&activation)?;
+
+    let mut tasks = tokio::task::JoinSet::new();
+
+    let live = LiveSettings::from(settings.clone());
+    let mut running = Vec::with_capacity(bound.len());
+
+    for (service, listener, args, tls) in bound {
+        let limits = Reloadable::new(args.limits.clone());
+        let server = Server::new(service.name(), &shutdown)
+            .with_limits(limits.clone())
+            .with_recorder(recorder.clone())
+            .with_tls(tls);
+        let live = live.clone();
+        tasks.spawn(async move { service.serve(listener, &server, &live).await });
+        running.push((service, args, limits));
+    }
+
+    let reloadable = planner.config.is_some();
+    let mut reloader = Reloader::new(Box::new(move || planner.plan()), running, settings, live);
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if reloadable {
+                info!("received SIGHUP, reloading the config");
+                reloader.reload();
+            } else {
+                warn!("received SIGHUP, but there is no --config to reload");
+            }
+        }
+    });
+
+    while let Some(result) = tasks.join_next().await {
+        if let Err(e) = result.expect("Server task panicked") {
+            // One broken service takes the others down with it
+            shutdown.trigger();
+            shutdown.drain(runtime.grace_period).await;
+            return Err(e);
+        }
+    }
+
+    shutdown.drain(runtime.grace_period).await;
+
+    Ok(())
+}
+
+#[cfg(feature = "blocking")]
+fn serve_blocking(
+    bound: Vec<(Service, Listener, ServerArgs, Option<TlsAcceptor>)>,
+    settings: Settings,
+) -> std::io::Result<()> {
+    let handles: Vec<_> = bound
+        .into_iter()
+        .map(|(service, listener, _, _)| {
+            let settings = settings.clone();
+            std::thread::spawn(move || service.serve_blocking(listener, settings))
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("Server thread panicked")?;
+    }
+
+    Ok(())
+}
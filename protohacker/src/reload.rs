@@ -0,0 +1,235 @@
+//! Reloading the config file on SIGHUP, without dropping any connection.
+//!
+//! Connection caps, read deadlines and the settings of the echo, chat, prime
+//! and mitm protocols apply live, to the connections opened after the reload.
+//! Anything that needs new sockets or a new TLS setup only applies on
+//! restart, and is reported as such.
+
+use protocore::cli::ServerArgs;
+use protocore::limits::Limits;
+use protocore::reload::Reloadable;
+use tracing::{error, info, warn};
+
+use crate::config::ConfigError;
+use crate::service::{LiveSettings, Service, Settings};
+
+/// Work out the services to run and their settings, from the config file and
+/// the flags.
+pub type Plan = Box<dyn Fn() -> Result<(Vec<(Service, ServerArgs)>, Settings), ConfigError> + Send>;
+
+/// Take the first of `services` of kind `service`, on `port` if given.
+fn take(
+    services: &mut [Option<(Service, ServerArgs)>],
+    service: Service,
+    port: Option<u16>,
+) -> Option<ServerArgs> {
+    services
+        .iter_mut()
+        .find(|new| {
+            matches!(new, Some((s, args))
+                if *s == service && port.is_none_or(|port| args.listen.port == port))
+        })
+        .and_then(Option::take)
+        .map(|(_, args)| args)
+}
+
+/// The settings the services run with, along with the handles to change them.
+pub struct Reloader {
+    plan: Plan,
+    /// Every service, with its arguments as currently applied.
+    services: Vec<(Service, ServerArgs, Reloadable<Limits>)>,
+    settings: Settings,
+    live: LiveSettings,
+}
+
+/// Names of the settings that changed, as written in the config file.
+#[derive(Default)]
+struct Changes {
+    applied: Vec<String>,
+    on_restart: Vec<String>,
+}
+
+impl Changes {
+    fn applied(&mut self, changed: bool, setting: String) -> bool {
+        if changed {
+            self.applied.push(setting);
+        }
+        changed
+    }
+
+    fn on_restart(&mut self, changed: bool, setting: String) {
+        if changed {
+            self.on_restart.push(setting);
+        }
+    }
+}
+
+impl Reloader {
+    pub fn new(
+        plan: Plan,
+        services: Vec<(Service, ServerArgs, Reloadable<Limits>)>,
+        settings: Settings,
+        live: LiveSettings,
+    ) -> Reloader {
+        Reloader {
+            plan,
+            services,
+            settings,
+            live,
+        }
+    }
+
+    /// Read the config file again and apply what can be, logging what
+    /// changed. An invalid file changes nothing.
+    pub fn reload(&mut self) {
+        let (services, settings) = match (self.plan)() {
+            Ok(plan) => plan,
+            Err(e) => {
+                error!("invalid config, keeping the current one, {e}");
+                return;
+            }
+        };
+
+        let mut changes = Changes::default();
+
+        // Services are told apart by what they run and their port, so that
+        // several of a kind keep their own settings. One left without a match
+        // is taken to have moved to another port of the same kind, any other
+        // added or removed needs new sockets
+        let mut services: Vec<_> = services.into_iter().map(Some).collect();
+        let matched: Vec<_> = self
+            .services
+            .iter()
+            .map(|(service, current, _)| take(&mut services, *service, Some(current.listen.port)))
+            .collect();
+        let matched: Vec<_> = self
+            .services
+            .iter()
+            .zip(matched)
+            .map(|((service, _, _), new)| new.or_else(|| take(&mut services, *service, None)))
+            .collect();
+
+        for ((service, current, limits), new) in self.services.iter_mut().zip(matched) {
+            let Some(new) = new else {
+                changes.on_restart(true, format!("services.{}", service.name()));
+                continue;
+            };
+
+            let name = service.name();
+            let table = |key: &str| format!("services.{name}.{key}");
+
+            changes.on_restart(current.listen.port != new.listen.port, table("port"));
+            changes.on_restart(current.listen.listen != new.listen.listen, table("listen"));
+            changes.on_restart(current.listen.family != new.listen.family, table("family"));
+            changes.on_restart(current.tls != new.tls, table("tls_cert"));
+
+            let (old, new) = (&current.limits, new.limits);
+            let differs = [
+                (old.max_clients != new.max_clients, "max_clients"),
+                (
+                    old.max_clients_per_ip != new.max_clients_per_ip,
+                    "max_clients_per_ip",
+                ),
+                (old.idle_timeout != new.idle_timeout, "idle_timeout"),
+                (old.read_timeout != new.read_timeout, "read_timeout"),
+            ];
+            let mut changed = false;
+            for (differs, key) in differs {
+                changed |= changes.applied(differs, table(key));
+            }
+            if changed {
+                limits.set(new.clone());
+                current.limits = new;
+            }
+        }
+        for (service, _) in services.into_iter().flatten() {
+            changes.on_restart(true, format!("services.{}", service.name()));
+        }
+
+        let (current, new) = (&mut self.settings, settings);
+
+        let (old, echo) = (&current.echo, &new.echo);
+        let differs = [
+            (old.buffer_size != echo.buffer_size, "buffer_size"),
+            (old.splice != echo.splice, "splice"),
+            (old.delay != echo.delay, "delay"),
+            (old.throttle != echo.throttle, "throttle"),
+            (old.fragment != echo.fragment, "fragment"),
+            (old.transform != echo.transform, "transform"),
+            (old.listeners != echo.listeners, "listen"),
+        ];
+        let mut changed = false;
+        for (differs, key) in differs {
+            changed |= changes.applied(differs, format!("services.echo.{key}"));
+        }
+        if changed {
+            self.live.echo.set(new.echo.clone());
+            current.echo = new.echo;
+        }
+
+        let prime = current.prime.max_request_size != new.prime.max_request_size;
+        if changes.applied(prime, "services.prime.max_request_size".to_string()) {
+            self.live.prime.set(new.prime.clone());
+            current.prime = new.prime;
+        }
+
+        let welcome = current.chat.welcome != new.chat.welcome;
+        let line = current.chat.max_line_size != new.chat.max_line_size;
+        changes.applied(welcome, "services.chat.welcome".to_string());
+        changes.applied(line, "services.chat.max_line_size".to_string());
+        if welcome || line {
+            self.live.chat.set(new.chat.clone());
+            current.chat = new.chat;
+        }
+
+        changes.on_restart(
+            current.kv.max_datagram_size != new.kv.max_datagram_size,
+            "services.kv.max_datagram_size".to_string(),
+        );
+
+        // The TLS setup of the upstream is kept, the rest goes live
+        let (current, mut new) = (&mut current.mitm, new.mitm);
+        changes.on_restart(
+            current.upstream.tls != new.upstream.tls,
+            "services.mitm.upstream".to_string(),
+        );
+        changes.on_restart(
+            current.upstream.ca != new.upstream.ca,
+            "services.mitm.upstream_ca".to_string(),
+        );
+        new.upstream.tls = current.upstream.tls;
+        new.upstream.ca = current.upstream.ca.clone();
+
+        let mitm = [
+            (current.upstream != new.upstream, "upstream"),
+            (current.address != new.address, "address"),
+            (current.pattern.as_str() != new.pattern.as_str(), "pattern"),
+            (current.max_line_size != new.max_line_size, "max_line_size"),
+            (
+                current.upstream_timeout != new.upstream_timeout,
+                "upstream_timeout",
+            ),
+        ];
+        let mut changed = false;
+        for (differs, key) in mitm {
+            changed |= changes.applied(differs, format!("services.mitm.{key}"));
+        }
+        if changed {
+            self.live.mitm.set(new.clone());
+            *current = new;
+        }
+
+        if changes.applied.is_empty() && changes.on_restart.is_empty() {
+            info!("reloaded the config, nothing changed");
+        }
+        if !changes.applied.is_empty() {
+            info!(settings = changes.applied.join(", "), "reloaded the config");
+        }
+        if !changes.on_restart.is_empty() {
+            warn!(
+                settings = changes.on_restart.join(", "),
+                "changed in the config, only applied on restart"
+            );
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! `protohacker replay`: re-drive a recorded session against a server and
+//! report where its replies differ from the recorded ones.
+
+use clap::Args;
+use protocore::cli::parse_duration;
+use protocore::record::{read_events, Event, EventKind};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+#[derive(Args, Clone)]
+pub struct ReplayArgs {
+    /// Recording made with `--record`
+    file: PathBuf,
+
+    /// Server to replay the recording against
+    #[arg(short, long, value_name = "HOST:PORT")]
+    target: String,
+
+    /// Only replay the traffic of this service, needed when the recording has several
+    #[arg(short, long)]
+    service: Option<String>,
+
+    /// How long to wait for each expected reply
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    timeout: Duration,
+}
+
+/// Show bytes as text where they are printable.
+fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+/// Replays the events of a recording one at a time, in their recorded order,
+/// so replies that depend on other clients (e.g. in the chat) line up.
+struct Replayer {
+    target: SocketAddr,
+    timeout: Duration,
+    streams: HashMap<u64, TcpStream>,
+    /// A socket per recorded peer, so the target sees distinct clients.
+    sockets: HashMap<SocketAddr, UdpSocket>,
+    mismatches: usize,
+    connections: usize,
+    datagrams: usize,
+}
+
+impl Replayer {
+    async fn apply(&mut self, event: &Event) -> std::io::Result<()> {
+        let data = event
+            .bytes()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        match (event.conn, event.kind) {
+            (Some(conn), EventKind::Open) => {
+                let stream = TcpStream::connect(self.target).await?;
+                self.streams.insert(conn, stream);
+                self.connections += 1;
+            }
+            (Some(conn), EventKind::Close) => {
+                self.streams.remove(&conn);
+            }
+            (Some(conn), EventKind::In) => {
+                if let Some(stream) = self.streams.get_mut(&conn) {
+                    stream.write_all(&data).await?;
+                }
+            }
+            (Some(conn), EventKind::Out) => {
+                let Some(stream) = self.streams.get_mut(&conn) else {
+                    return Ok(());
+                };
+
+                let mut actual = vec![0; data.len()];
+                let mut filled = 0;
+                while filled < actual.len() {
+                    let read =
+                        tokio::time::timeout(self.timeout, stream.read(&mut actual[filled..]));
+                    match read.await {
+                        Ok(Ok(0)) | Err(_) => break,
+                        Ok(Ok(n)) => filled += n,
+                        Ok(Err(e)) => return Err(e),
+                    }
+                }
+                actual.truncate(filled);
+
+                self.check(format!("connection {conn} ({})", event.peer), data, actual);
+            }
+            (None, EventKind::In) => {
+                let socket = self.socket(event.peer).await?;
+                socket.send(&data).await?;
+                self.datagrams += 1;
+            }
+            (None, EventKind::Out) => {
+                let timeout = self.timeout;
+                let socket = self.socket(event.peer).await?;
+
+                let mut actual = vec![0; 65536];
+                let n = match tokio::time::timeout(timeout, socket.recv(&mut actual)).await {
+                    Ok(received) => received?,
+                    Err(_) => 0,
+                };
+                actual.truncate(n);
+
+                self.check(format!("datagrams of {}", event.peer), data, actual);
+            }
+            (None, _) => {}
+        }
+
+        Ok(())
+    }
+
+    async fn socket(&mut self, peer: SocketAddr) -> std::io::Result<&UdpSocket> {
+        if !self.sockets.contains_key(&peer) {
+            let local: SocketAddr = match self.target {
+                SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+            };
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(self.target).await?;
+            self.sockets.insert(peer, socket);
+        }
+
+        Ok(&self.sockets[&peer])
+    }
+
+    /// Report a reply differing from the recorded one. `session` tells which
+    /// connection, or which datagram peer, it came from.
+    fn check(&mut self, session: String, expected: Vec<u8>, actual: Vec<u8>) {
+        if expected != actual {
+            println!(
+                "{session}: expected \"{}\", got \"{}\"",
+                escape(&expected),
+                escape(&actual)
+            );
+            self.mismatches += 1;
+        }
+    }
+}
+
+/// The services found in a recording, in order of appearance.
+fn services(events: &[Event]) -> Vec<&str> {
+    let mut services: Vec<&str> = Vec::new();
+    for event in events {
+        if !services.contains(&event.service.as_str()) {
+            services.push(&event.service);
+        }
+    }
+    services
+}
+
+async fn replay(args: ReplayArgs) -> std::io::Result<bool> {
+    let mut events = read_events(&args.file)?;
+
+    match &args.service {
+        Some(service) => events.retain(|event| &event.service == service),
+        None => {
+            let services = services(&events);
+            if services.len() > 1 {
+                eprintln!(
+                    "The recording has traffic for {}, pick one with --service",
+                    services.join(", ")
+                );
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let target = tokio::net::lookup_host(&args.target)
+        .await?
+        .next()
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} does not resolve", args.target),
+            )
+        })?;
+
+    let mut replayer = Replayer {
+        target,
+        timeout: args.timeout,
+        streams: HashMap::new(),
+        sockets: HashMap::new(),
+        mismatches: 0,
+        connections: 0,
+        datagrams: 0,
+    };
+
+    for event in &events {
+        replayer.apply(event).await?;
+    }
+
+    println!(
+        "Replayed {} connection(s) and {} datagram(s) against {target}: {} mismatch(es)",
+        replayer.connections, replayer.datagrams, replayer.mismatches
+    );
+
+    Ok(replayer.mismatches == 0)
+}
+
+/// Run the replay, exiting with 1 if any reply differs.
+pub fn run(args: ReplayArgs) -> std::io::Result<()> {
+    let matched = tokio::runtime::Runtime::new()?.block_on(replay(args))?;
+
+    if !matched {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
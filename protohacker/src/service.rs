@@ -0,0 +1,218 @@
+use clap::ValueEnum;
+use protocore::activation::Activation;
+use protocore::cli::{IpProtocol, ListenAddr, ListenArgs, Listeners};
+use protocore::net::Server;
+use protocore::reload::Reloadable;
+use serde::Deserialize;
+use std::net::{TcpListener, UdpSocket};
+use std::str::FromStr;
+
+/// The servers this binary knows how to run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    /// Smoke test (protohacker0)
+    Echo,
+    /// Prime time (protohacker1)
+    Prime,
+    /// Means to an end (protohacker2)
+    Means,
+    /// Budget chat (protohacker3)
+    Chat,
+    /// Unusual database program (protohacker4)
+    Kv,
+    /// Mob in the middle (protohacker5)
+    Mitm,
+}
+
+/// Protocol settings of every service, the defaults unless changed by the
+/// config file or the flags.
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    pub echo: protohacker0::Settings,
+    pub prime: protohacker1::Settings,
+    pub chat: protohacker3::Settings,
+    pub kv: protohacker4::Settings,
+    pub mitm: protohacker5::Settings,
+}
+
+/// The settings of the running services. Those of the TCP services can be
+/// changed while serving.
+#[derive(Clone, Debug, Default)]
+pub struct LiveSettings {
+    pub echo: Reloadable<protohacker0::Settings>,
+    pub prime: Reloadable<protohacker1::Settings>,
+    pub chat: Reloadable<protohacker3::Settings>,
+    /// Sizes the receive buffers, fixed once serving.
+    pub kv: protohacker4::Settings,
+    pub mitm: Reloadable<protohacker5::Settings>,
+}
+
+impl From<Settings> for LiveSettings {
+    fn from(settings: Settings) -> Self {
+        LiveSettings {
+            echo: settings.echo.into(),
+            prime: settings.prime.into(),
+            chat: settings.chat.into(),
+            kv: settings.kv,
+            mitm: settings.mitm.into(),
+        }
+    }
+}
+
+/// The bound sockets of a service, ready to be served.
+pub enum Listener {
+    Tcp(Vec<TcpListener>),
+    Udp(Vec<UdpSocket>),
+    /// Sockets of every kind for the echo, bound one listen address at a
+    /// time so each can have diagnostics of its own.
+    Echo(Vec<(Option<ListenAddr>, Listeners)>),
+}
+
+impl Service {
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::Echo => "echo",
+            Service::Prime => "prime",
+            Service::Means => "means",
+            Service::Chat => "chat",
+            Service::Kv => "kv",
+            Service::Mitm => "mitm",
+        }
+    }
+
+    /// The protocol of the IP addresses given without one.
+    pub fn protocol(self) -> IpProtocol {
+        match self {
+            Service::Kv => IpProtocol::Udp,
+            _ => IpProtocol::Tcp,
+        }
+    }
+
+    /// Bind the sockets the service listens on.
+    pub fn bind(
+        self,
+        listen: &ListenArgs,
+        activation: &mut Activation,
+    ) -> std::io::Result<Listener> {
+        match self {
+            Service::Echo if listen.listen.is_empty() => Ok(Listener::Echo(vec![(
+                None,
+                listen.bind(self.protocol(), activation)?,
+            )])),
+            Service::Echo => {
+                let mut bound = Vec::with_capacity(listen.listen.len());
+                for addr in &listen.listen {
+                    let one = ListenArgs {
+                        listen: vec![addr.clone()],
+                        ..listen.clone()
+                    };
+                    bound.push((Some(addr.clone()), one.bind(self.protocol(), activation)?));
+                }
+                Ok(Listener::Echo(bound))
+            }
+            Service::Kv => Ok(Listener::Udp(listen.bind_udp(activation)?)),
+            _ => Ok(Listener::Tcp(listen.bind_tcp(activation)?)),
+        }
+    }
+
+    /// Serve the service on the sockets obtained from `bind`, until the
+    /// server's shutdown is triggered or a socket fails. Only the settings of
+    /// the service itself are used.
+    pub async fn serve(
+        self,
+        listener: Listener,
+        server: &Server,
+        settings: &LiveSettings,
+    ) -> std::io::Result<()> {
+        match (self, listener) {
+            (Service::Echo, Listener::Echo(l)) => {
+                protohacker0::serve_listeners(server, l, settings.echo.clone()).await
+            }
+            (Service::Prime, Listener::Tcp(l)) => {
+                protohacker1::serve_with(server, l, settings.prime.clone()).await
+            }
+            (Service::Means, Listener::Tcp(l)) => protohacker2::serve(server, l).await,
+            (Service::Chat, Listener::Tcp(l)) => {
+                protohacker3::serve_with(server, l, settings.chat.clone()).await
+            }
+            (Service::Kv, Listener::Udp(socks)) => {
+                protohacker4::serve_with(server, socks, settings.kv.clone()).await
+            }
+            (Service::Mitm, Listener::Tcp(l)) => {
+                protohacker5::serve_with(server, l, settings.mitm.clone()).await
+            }
+            (service, _) => panic!("{} was bound to the wrong kind of socket", service.name()),
+        }
+    }
+
+    /// Same as `serve`, with the thread-per-connection implementation.
+    #[cfg(feature = "blocking")]
+    pub fn serve_blocking(self, listener: Listener, settings: Settings) -> std::io::Result<()> {
+        use protocore::blocking::net::{serve_tcp, serve_udp};
+
+        match (self, listener) {
+            (Service::Echo, Listener::Echo(l)) => {
+                let mut tcp = Vec::new();
+                for (_, l) in l {
+                    if !(l.udp.is_empty() && l.unix.is_empty() && l.unix_datagram.is_empty()) {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            "the blocking echo only listens on TCP, drop --blocking for the others",
+                        ));
+                    }
+                    tcp.extend(l.tcp);
+                }
+                serve_tcp(tcp, protohacker0::blocking::handle_echo)
+            }
+            (Service::Prime, Listener::Tcp(l)) => serve_tcp(l, move |stream| {
+                protohacker1::blocking::handle_stream_with(stream, &settings.prime)
+            }),
+            (Service::Means, Listener::Tcp(l)) => {
+                serve_tcp(l, protohacker2::blocking::handle_stream)
+            }
+            (Service::Chat, Listener::Tcp(l)) => {
+                let chat = protohacker3::blocking::BudgetChat::with_settings(settings.chat);
+                serve_tcp(l, move |stream| chat.handle_stream(stream))
+            }
+            (Service::Kv, Listener::Udp(socks)) => {
+                let database = protohacker4::Database::with_settings(&settings.kv);
+                let database = std::sync::Mutex::new(database);
+                serve_udp(socks, settings.kv.max_datagram_size, move |request, _| {
+                    database.lock().unwrap().handle_datagram(request)
+                })
+            }
+            (Service::Mitm, Listener::Tcp(l)) => {
+                let mitm = std::sync::Arc::new(settings.mitm);
+                serve_tcp(l, move |stream| {
+                    protohacker5::blocking::establish_proxy(stream, &mitm)
+                })
+            }
+            (service, _) => panic!("{} was bound to the wrong kind of socket", service.name()),
+        }
+    }
+}
+
+/// A `service=port` pair, as given to the `run` subcommand.
+#[derive(Clone, Copy, Debug)]
+pub struct ServiceSpec {
+    pub service: Service,
+    pub port: u16,
+}
+
+impl FromStr for ServiceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, port) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected SERVICE=PORT, got `{s}`"))?;
+
+        let service = Service::from_str(name, true)?;
+        let port = port
+            .parse()
+            .map_err(|e| format!("invalid port `{port}`: {e}"))?;
+
+        Ok(ServiceSpec { service, port })
+    }
+}
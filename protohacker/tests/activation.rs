@@ -0,0 +1,89 @@
+//! Starting the `protohacker` binary the way a service manager does: with
+//! its listeners passed down, or as root to switch user once bound.
+
+mod common;
+
+use common::Binary;
+use std::net::{Ipv4Addr, TcpListener};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+#[tokio::test]
+async fn serves_the_listeners_passed_by_systemd() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.as_raw_fd();
+
+    // The shell keeps its pid when it execs the binary, as systemd does
+    // between setting LISTEN_PID and running the service
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\" \"$@\""])
+        .arg(env!("CARGO_BIN_EXE_protohacker"))
+        .args([
+            "echo",
+            "--listen",
+            "127.0.0.1",
+            "-p",
+            &addr.port().to_string(),
+        ])
+        .stderr(Stdio::null());
+    // SAFETY: only async-signal-safe calls between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            let moved = if fd == 3 {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            match moved {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        });
+    }
+    let server = Binary(command.spawn().unwrap());
+
+    // We still hold the listener, so the binary cannot have bound its own
+    let mut client = server.connect(addr.port()).await;
+    client.send("passed down\n").await;
+    client.expect("passed down\n").await;
+}
+
+#[tokio::test]
+async fn switches_user_once_bound() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("not root, nothing to drop");
+        return;
+    }
+
+    let nobody = Command::new("id").args(["-u", "nobody"]).output().unwrap();
+    let nobody = String::from_utf8(nobody.stdout).unwrap();
+
+    let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server = Binary::command()
+        .args(["echo", "--listen", "127.0.0.1", "-p", &port.to_string()])
+        .args(["--user", "nobody"])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let server = Binary(server);
+
+    let mut client = server.connect(port).await;
+    client.send("hi\n").await;
+    client.expect("hi\n").await;
+
+    // Real, effective, saved and filesystem ids all switched
+    let status = std::fs::read_to_string(format!("/proc/{}/status", server.0.id())).unwrap();
+    let uids = status
+        .lines()
+        .find(|line| line.starts_with("Uid:"))
+        .unwrap();
+    let uids: Vec<_> = uids.split_whitespace().skip(1).collect();
+    assert_eq!(uids, [nobody.trim(); 4]);
+}
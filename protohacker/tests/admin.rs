@@ -0,0 +1,116 @@
+//! Commands of the admin socket, against the services of this process.
+
+mod common;
+
+use common::{socket_path, start_tcp, start_udp, Client, Datagrams, TempPath};
+use protocore::shutdown::Shutdown;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// The admin socket of the test `name`, closed when dropped.
+struct Admin {
+    path: TempPath,
+    shutdown: Shutdown,
+}
+
+impl Drop for Admin {
+    fn drop(&mut self) {
+        self.shutdown.trigger();
+    }
+}
+
+impl Admin {
+    fn spawn(name: &str) -> Admin {
+        let path = socket_path(name);
+        let shutdown = Shutdown::new();
+        protocore::admin::spawn_socket(&path, &shutdown).expect("failed to bind the admin socket");
+        Admin { path, shutdown }
+    }
+
+    /// Send a command, returning the answer.
+    async fn run(&self, command: &str) -> String {
+        let mut stream = UnixStream::connect(&self.path).await.unwrap();
+        stream
+            .write_all(format!("{command}\n").as_bytes())
+            .await
+            .unwrap();
+
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer).await.unwrap();
+        answer
+    }
+}
+
+/// Connect and join the chat room as `name`.
+async fn join(addr: std::net::SocketAddr, name: &str, room: &str) -> Client {
+    let mut client = Client::connect(addr).await;
+    client.read_line().await;
+    client.send(format!("{name}\n")).await;
+    client
+        .expect(format!("*Welcome. Users in room: {room}\n"))
+        .await;
+    client
+}
+
+// The only test with a chat server, so the chat connections listed are its own
+#[tokio::test]
+async fn kicks_a_chat_user_out_of_the_room() {
+    let server = start_tcp("chat", |server, listeners| async move {
+        protohacker3::serve(&server, listeners).await
+    });
+    let admin = Admin::spawn("kick");
+
+    let mut alice = join(server.addr, "alice", "").await;
+    let mut bob = join(server.addr, "bob", ", alice").await;
+    alice.expect("* bob has joined the room\n").await;
+
+    assert_eq!(admin.run("dump chat").await, "alice\nbob\n");
+
+    let connections = admin.run("connections chat").await;
+    let lines: Vec<_> = connections.lines().collect();
+    assert_eq!(lines.len(), 2, "unexpected connections {connections:?}");
+    assert!(lines.iter().all(|line| line.contains(" chat 127.0.0.1:")));
+
+    // Ids go up, bob connected last
+    let bob_id = lines[1].split(' ').next().unwrap();
+    assert_eq!(
+        admin.run(&format!("kick {bob_id}")).await,
+        format!("kicked {bob_id}\n")
+    );
+
+    bob.expect_closed().await;
+    alice.expect("* bob has left the room\n").await;
+    assert_eq!(admin.run("dump chat").await, "alice\n");
+    assert_eq!(admin.run("connections chat").await.lines().count(), 1);
+}
+
+#[tokio::test]
+async fn dumps_the_keys_of_kv() {
+    let server = start_udp("kv", |server, sockets| async move {
+        protohacker4::serve(&server, sockets).await
+    });
+    let admin = Admin::spawn("dump");
+    let client = Datagrams::connect(server.addr).await;
+
+    client.send("foo=bar\nbaz").await;
+    client.send("foo").await;
+    client.expect("foo=bar\nbaz").await;
+
+    assert_eq!(admin.run("dump kv").await, "foo=bar\\nbaz\nversion=1.0\n");
+}
+
+#[tokio::test]
+async fn refuses_bad_commands() {
+    let admin = Admin::spawn("bad");
+
+    assert_eq!(admin.run("kick 0").await, "error: no connection 0\n");
+    assert_eq!(
+        admin.run("kick bob").await,
+        "error: `bob` is not a connection id\n"
+    );
+    assert_eq!(
+        admin.run("dump echo").await,
+        "error: echo has nothing to dump\n"
+    );
+    assert!(admin.run("reboot").await.starts_with("error: expected"));
+}
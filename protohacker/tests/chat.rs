@@ -0,0 +1,117 @@
+//! Budget chat scenarios.
+
+mod common;
+
+use common::{start_tcp, Client};
+
+const WELCOME: &str = "Welcome to budgetchat! What shall I call you?\n";
+
+async fn start() -> common::Running {
+    start_tcp("chat", |server, listeners| async move {
+        protohacker3::serve(&server, listeners).await
+    })
+}
+
+/// Connect and join the room as `name`, checking the welcome on the way.
+async fn join(addr: std::net::SocketAddr, name: &str, room: &str) -> Client {
+    let mut client = Client::connect(addr).await;
+    client.expect(WELCOME).await;
+    client.send(format!("{name}\n")).await;
+    client
+        .expect(format!("*Welcome. Users in room: {room}\n"))
+        .await;
+    client
+}
+
+#[tokio::test]
+async fn example_session() {
+    let server = start().await;
+
+    let mut alice = join(server.addr, "alice", "").await;
+    let mut bob = join(server.addr, "bob", ", alice").await;
+    alice.expect("* bob has joined the room\n").await;
+
+    bob.send("Hi alice\n").await;
+    alice.expect("[bob] Hi alice\n").await;
+
+    alice.send("Hello bob\n").await;
+    bob.expect("[alice] Hello bob\n").await;
+
+    drop(bob);
+    alice.expect("* bob has left the room\n").await;
+}
+
+#[tokio::test]
+async fn lists_everyone_already_in_the_room() {
+    let server = start().await;
+
+    let _alice = join(server.addr, "alice", "").await;
+    let _bob = join(server.addr, "bob", ", alice").await;
+
+    // The room is a hash map, so the order of the names is not fixed
+    let mut carol = Client::connect(server.addr).await;
+    carol.expect(WELCOME).await;
+    carol.send("carol\n").await;
+    carol.expect("*Welcome. Users in room: , ").await;
+
+    let names = carol.read_line().await;
+    assert!(
+        names == "alice, bob\n" || names == "bob, alice\n",
+        "unexpected room listing {names:?}"
+    );
+}
+
+#[tokio::test]
+async fn messages_are_not_echoed_to_their_sender() {
+    let server = start().await;
+
+    let mut alice = join(server.addr, "alice", "").await;
+    let mut bob = join(server.addr, "bob", ", alice").await;
+    alice.expect("* bob has joined the room\n").await;
+
+    alice.send("first\n").await;
+    bob.send("second\n").await;
+
+    bob.expect("[alice] first\n").await;
+    alice.expect("[bob] second\n").await;
+}
+
+#[tokio::test]
+async fn rejects_invalid_names() {
+    let server = start().await;
+
+    for name in ["", "al ice", "älice", "alice!"] {
+        let mut client = Client::connect(server.addr).await;
+        client.expect(WELCOME).await;
+        client.send(format!("{name}\n")).await;
+        client.expect_closed().await;
+    }
+}
+
+#[tokio::test]
+async fn rejects_names_already_taken() {
+    let server = start().await;
+
+    let _alice = join(server.addr, "alice", "").await;
+
+    let mut impostor = Client::connect(server.addr).await;
+    impostor.expect(WELCOME).await;
+    impostor.send("alice\n").await;
+    impostor.expect_closed().await;
+}
+
+#[tokio::test]
+async fn users_who_never_joined_are_not_announced() {
+    let server = start().await;
+
+    let mut alice = join(server.addr, "alice", "").await;
+
+    let mut lurker = Client::connect(server.addr).await;
+    lurker.expect(WELCOME).await;
+    drop(lurker);
+
+    let mut bob = join(server.addr, "bob", ", alice").await;
+    alice.expect("* bob has joined the room\n").await;
+    bob.send("hi\n").await;
+    alice.expect("[bob] hi\n").await;
+}
@@ -0,0 +1,110 @@
+//! The reference clients, against the servers they were written for.
+
+mod common;
+
+use common::{start_tcp, start_udp};
+use protoclient::{ChatClient, ClientError, KvClient, MeansClient, PrimeClient};
+use protohacker3::Event;
+
+#[tokio::test]
+async fn prime_client() {
+    let server = start_tcp("prime", |server, listeners| async move {
+        protohacker1::serve(&server, listeners).await
+    });
+    let mut client = PrimeClient::connect(server.addr).await.unwrap();
+
+    assert!(client.is_prime(7.0).await.unwrap());
+    assert!(!client.is_prime(8.0).await.unwrap());
+    assert!(!client.is_prime(7.5).await.unwrap());
+}
+
+#[tokio::test]
+async fn means_client() {
+    let server = start_tcp("means", |server, listeners| async move {
+        protohacker2::serve(&server, listeners).await
+    });
+    let mut client = MeansClient::connect(server.addr).await.unwrap();
+
+    client.insert(12345, 101).await.unwrap();
+    client.insert(12346, 102).await.unwrap();
+    client.insert(12347, 100).await.unwrap();
+    client.insert(40960, 5).await.unwrap();
+
+    assert_eq!(client.query(12288, 16384).await.unwrap(), 101);
+}
+
+#[tokio::test]
+async fn chat_client() {
+    let server = start_tcp("chat", |server, listeners| async move {
+        protohacker3::serve(&server, listeners).await
+    });
+
+    let mut alice = ChatClient::join(server.addr, "alice").await.unwrap();
+    assert!(alice.members().is_empty());
+
+    let mut bob = ChatClient::join(server.addr, "bob").await.unwrap();
+    assert_eq!(bob.members(), ["alice"]);
+    assert!(matches!(
+        ChatClient::join(server.addr, "bob").await,
+        Err(ClientError::Rejected)
+    ));
+
+    let joined = alice.next_event().await.unwrap();
+    assert_eq!(joined, Some(Event::Joined("bob".to_string())));
+
+    bob.send("hi alice").await.unwrap();
+    let sent = alice.next_event().await.unwrap();
+    assert_eq!(sent, Some(Event::Sent("bob".into(), "hi alice".into())));
+
+    drop(bob);
+    let left = alice.next_event().await.unwrap();
+    assert_eq!(left, Some(Event::Left("bob".to_string())));
+}
+
+#[tokio::test]
+async fn chat_client_takes_any_welcome() {
+    let server = start_tcp("chat", |server, listeners| async move {
+        let settings = protohacker3::Settings {
+            welcome: "Halt! Who goes there?".to_string(),
+            ..protohacker3::Settings::default()
+        };
+        protohacker3::serve_with(&server, listeners, settings).await
+    });
+
+    let alice = ChatClient::join(server.addr, "alice").await.unwrap();
+    assert!(alice.members().is_empty());
+}
+
+#[tokio::test]
+async fn kv_client() {
+    let server = start_udp("kv", |server, sockets| async move {
+        protohacker4::serve(&server, sockets).await
+    });
+    let client = KvClient::connect(server.addr).await.unwrap();
+
+    assert_eq!(client.version().await.unwrap(), "1.0");
+    assert_eq!(client.get("foo").await.unwrap(), "");
+
+    client.set("foo", "bar=baz").await.unwrap();
+    assert_eq!(client.get("foo").await.unwrap(), "bar=baz");
+
+    assert!(matches!(
+        client.set("a=b", "c").await,
+        Err(ClientError::InvalidKey(_))
+    ));
+}
+
+#[tokio::test]
+async fn kv_client_reads_long_values() {
+    let server = start_udp("kv", |server, sockets| async move {
+        let settings = protohacker4::Settings {
+            max_datagram_size: 65507,
+        };
+        protohacker4::serve_with(&server, sockets, settings).await
+    });
+    let client = KvClient::connect(server.addr).await.unwrap();
+
+    let value = "v".repeat(5000);
+    client.set("long", &value).await.unwrap();
+    assert_eq!(client.get("long").await.unwrap(), value);
+}
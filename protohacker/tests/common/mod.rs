@@ -0,0 +1,284 @@
+//! Harness for the integration tests: serve a protohacker on an ephemeral
+//! port of the loopback and talk to it as the checker would.
+
+#![allow(dead_code)] // each test binary uses its own subset
+
+use protocore::net::{bind_tcp, bind_udp, Server};
+use protocore::shutdown::Shutdown;
+use protocore::tls::{self, TlsAcceptor};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// How long to wait for a reply before failing the test.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Self-signed certificate of the TLS tests, for `localhost` and `127.0.0.1`.
+pub const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/cert.pem");
+/// Private key of [`CERT`].
+pub const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/key.pem");
+
+/// Terminate TLS with the fixture certificate.
+pub fn tls_acceptor() -> TlsAcceptor {
+    tls::acceptor(Path::new(CERT), Path::new(KEY)).expect("failed to load the fixtures")
+}
+
+fn loopback() -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, 0).into()
+}
+
+/// A server running in the background, shut down when dropped.
+pub struct Running {
+    pub addr: SocketAddr,
+    shutdown: Shutdown,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.shutdown.trigger();
+    }
+}
+
+/// Serve a TCP protohacker with `serve`, e.g. `protohacker0::serve`.
+pub fn start_tcp<S, Fut>(name: &'static str, serve: S) -> Running
+where
+    S: FnOnce(Server, Vec<TcpListener>) -> Fut,
+    Fut: Future<Output = std::io::Result<()>> + Send + 'static,
+{
+    let listener = bind_tcp(loopback(), true).expect("failed to bind a listener");
+    let addr = listener.local_addr().unwrap();
+
+    let shutdown = Shutdown::new();
+    let server = Server::new(name, &shutdown);
+    tokio::spawn(serve(server, vec![listener]));
+
+    Running { addr, shutdown }
+}
+
+/// Serve a UDP protohacker with `serve`, e.g. `protohacker4::serve`.
+pub fn start_udp<S, Fut>(name: &'static str, serve: S) -> Running
+where
+    S: FnOnce(Server, Vec<UdpSocket>) -> Fut,
+    Fut: Future<Output = std::io::Result<()>> + Send + 'static,
+{
+    let socket = bind_udp(loopback(), true).expect("failed to bind a socket");
+    let addr = socket.local_addr().unwrap();
+
+    let shutdown = Shutdown::new();
+    let server = Server::new(name, &shutdown);
+    tokio::spawn(serve(server, vec![socket]));
+
+    Running { addr, shutdown }
+}
+
+/// A temporary file of a test, removed when dropped.
+pub struct TempPath(PathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // Not created by every test, or already removed by a server
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl std::ops::Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+/// A path for the file `name` of a test, apart from the other test binaries
+/// running at the same time.
+pub fn temp_path(name: &str) -> TempPath {
+    TempPath(std::env::temp_dir().join(format!("protohacker-{}-{name}", std::process::id())))
+}
+
+/// A path for a Unix socket of the test `name`.
+pub fn socket_path(name: &str) -> TempPath {
+    temp_path(&format!("{name}.sock"))
+}
+
+/// A port nothing listens on, most likely still free once returned.
+pub fn free_port() -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// The `protohacker` binary serving in the background, killed when dropped.
+pub struct Binary(pub Child);
+
+impl Drop for Binary {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Binary {
+    pub fn command() -> Command {
+        Command::new(env!("CARGO_BIN_EXE_protohacker"))
+    }
+
+    pub fn spawn(args: &[&str]) -> Binary {
+        let child = Binary::command().args(args).stderr(Stdio::null()).spawn();
+        Binary(child.unwrap())
+    }
+
+    /// Connect to `port` of the loopback once the binary listens on it.
+    pub async fn connect(&self, port: u16) -> Client {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let deadline = Instant::now() + TIMEOUT;
+
+        while TcpStream::connect(addr).await.is_err() {
+            assert!(Instant::now() < deadline, "nothing listens on {addr}");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        Client::connect(addr).await
+    }
+
+    /// Have the binary reload its config file.
+    pub fn hangup(&self) {
+        let pid = self.0.id().to_string();
+        let status = Command::new("kill").args(["-HUP", &pid]).status().unwrap();
+        assert!(status.success(), "failed to signal {pid}");
+    }
+}
+
+/// What a client talks over: plain TCP or TLS.
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// A TCP client asserting on the exact bytes it receives.
+pub struct Client {
+    stream: Box<dyn Transport>,
+}
+
+impl Client {
+    pub async fn connect(addr: SocketAddr) -> Client {
+        let stream = TcpStream::connect(addr).await.expect("failed to connect");
+        Client {
+            stream: Box::new(stream),
+        }
+    }
+
+    /// Connect over TLS, trusting the fixture certificate.
+    pub async fn connect_tls(addr: SocketAddr) -> Client {
+        let connector = tls::connector(Some(Path::new(CERT))).expect("failed to load the fixtures");
+        let name = tls::server_name(&addr.ip().to_string()).unwrap();
+
+        let stream = TcpStream::connect(addr).await.expect("failed to connect");
+        let stream = connector
+            .connect(name, stream)
+            .await
+            .expect("failed to establish TLS");
+        Client {
+            stream: Box::new(stream),
+        }
+    }
+
+    pub async fn send(&mut self, bytes: impl AsRef<[u8]>) {
+        self.stream
+            .write_all(bytes.as_ref())
+            .await
+            .expect("failed to send");
+    }
+
+    /// Close our side of the connection, the server can still reply.
+    pub async fn finish(&mut self) {
+        self.stream.shutdown().await.expect("failed to shut down");
+    }
+
+    /// Read exactly as many bytes as `expected` holds and compare them.
+    pub async fn expect(&mut self, expected: impl AsRef<[u8]>) {
+        let expected = expected.as_ref();
+        let mut actual = vec![0; expected.len()];
+
+        tokio::time::timeout(TIMEOUT, self.stream.read_exact(&mut actual))
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for \"{}\"", expected.escape_ascii()))
+            .unwrap_or_else(|e| panic!("failed to read \"{}\": {e}", expected.escape_ascii()));
+
+        assert_eq!(
+            actual.escape_ascii().to_string(),
+            expected.escape_ascii().to_string()
+        );
+    }
+
+    /// Read up to the next newline, included.
+    pub async fn read_line(&mut self) -> String {
+        let mut line = Vec::new();
+        let mut byte = [0];
+
+        while !line.ends_with(b"\n") {
+            tokio::time::timeout(TIMEOUT, self.stream.read_exact(&mut byte))
+                .await
+                .expect("timed out waiting for a line")
+                .expect("failed to read a line");
+            line.push(byte[0]);
+        }
+
+        String::from_utf8(line).expect("the line is not utf-8")
+    }
+
+    /// Expect the server to close the connection without sending anything more.
+    pub async fn expect_closed(&mut self) {
+        let mut rest = Vec::new();
+
+        match tokio::time::timeout(TIMEOUT, self.stream.read_to_end(&mut rest)).await {
+            Err(_) => panic!("the server kept the connection open"),
+            // A reset also means the server is done with us
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionReset => {}
+            Ok(Err(e)) => panic!("failed to read: {e}"),
+            Ok(Ok(_)) => assert_eq!(rest.escape_ascii().to_string(), ""),
+        }
+    }
+}
+
+/// A UDP client, connected to a single server.
+pub struct Datagrams {
+    socket: tokio::net::UdpSocket,
+}
+
+impl Datagrams {
+    pub async fn connect(addr: SocketAddr) -> Datagrams {
+        let socket = tokio::net::UdpSocket::bind(loopback()).await.unwrap();
+        socket.connect(addr).await.expect("failed to connect");
+        Datagrams { socket }
+    }
+
+    pub async fn send(&self, datagram: impl AsRef<[u8]>) {
+        self.socket
+            .send(datagram.as_ref())
+            .await
+            .expect("failed to send");
+    }
+
+    /// Receive the next datagram and compare it to `expected`.
+    pub async fn expect(&self, expected: impl AsRef<[u8]>) {
+        let expected = expected.as_ref();
+        let mut actual = vec![0; 65536];
+
+        let n = tokio::time::timeout(TIMEOUT, self.socket.recv(&mut actual))
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for \"{}\"", expected.escape_ascii()))
+            .expect("failed to receive");
+
+        assert_eq!(
+            actual[..n].escape_ascii().to_string(),
+            expected.escape_ascii().to_string()
+        );
+    }
+}
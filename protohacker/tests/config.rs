@@ -0,0 +1,210 @@
+//! Running the `protohacker` binary from a config file.
+
+mod common;
+
+use common::{free_port, temp_path, Binary, TempPath};
+use std::process::Output;
+use std::time::{Duration, Instant};
+
+/// Write a config file for the test `name`.
+fn write_config(name: &str, contents: &str) -> TempPath {
+    let path = temp_path(&format!("{name}.toml"));
+    std::fs::write(&path, contents).expect("failed to write the config");
+    path
+}
+
+/// Run the binary to completion, expecting it to refuse the config.
+fn refused(args: &[&str]) -> String {
+    let Output { status, stderr, .. } = Binary::command().args(args).output().unwrap();
+    assert_eq!(status.code(), Some(2), "the config was accepted");
+    String::from_utf8_lossy(&stderr).into_owned()
+}
+
+#[tokio::test]
+async fn serves_the_settings_of_the_file() {
+    let port = free_port();
+    let config = write_config(
+        "settings",
+        &format!(
+            "[services.chat]\n\
+             port = {port}\n\
+             listen = [\"127.0.0.1\"]\n\
+             welcome = \"Who goes there?\"\n"
+        ),
+    );
+    let server = Binary::spawn(&["run", "--config", config.to_str().unwrap()]);
+
+    let mut client = server.connect(port).await;
+    client.expect("Who goes there?\n").await;
+}
+
+#[tokio::test]
+async fn flags_override_the_file() {
+    let (configured, given) = (free_port(), free_port());
+    let config = write_config(
+        "override",
+        &format!("[services.echo]\nport = {configured}\nlisten = [\"0.0.0.0\"]\n"),
+    );
+    let server = Binary::spawn(&[
+        "--config",
+        config.to_str().unwrap(),
+        "run",
+        &format!("echo={given}"),
+        "--listen",
+        "127.0.0.1",
+    ]);
+
+    let mut client = server.connect(given).await;
+    client.send("hello\n").await;
+    client.expect("hello\n").await;
+}
+
+#[tokio::test]
+async fn reloads_on_hangup_keeping_the_connections() {
+    let port = free_port();
+    let chat = |welcome: &str| {
+        format!(
+            "[services.chat]\nport = {port}\nlisten = [\"127.0.0.1\"]\nwelcome = \"{welcome}\"\n"
+        )
+    };
+    let config = write_config("reload", &chat("Who goes there?"));
+    let server = Binary::spawn(&["run", "--config", config.to_str().unwrap()]);
+
+    let mut alice = server.connect(port).await;
+    alice.expect("Who goes there?\n").await;
+    alice.send("alice\n").await;
+    alice.expect("*Welcome. Users in room: \n").await;
+
+    std::fs::write(&config, chat("Halt!")).unwrap();
+    server.hangup();
+
+    // The signal is handled in the background, newcomers get the new
+    // welcome soon after
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut bob = loop {
+        let mut bob = server.connect(port).await;
+        if bob.read_line().await == "Halt!\n" {
+            break bob;
+        }
+        assert!(Instant::now() < deadline, "the welcome was not reloaded");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+
+    bob.send("bob\n").await;
+    bob.expect("*Welcome. Users in room: , alice\n").await;
+    alice.expect("* bob has joined the room\n").await;
+}
+
+#[test]
+fn refuses_settings_of_another_service() {
+    let config = write_config("misplaced", "[services.echo]\nport = 1\nwelcome = \"hi\"\n");
+    let stderr = refused(&["--config", config.to_str().unwrap(), "run"]);
+
+    assert!(stderr.contains("[services.echo] `welcome` is not a setting of echo"));
+}
+
+#[test]
+fn points_at_the_line_of_bad_values() {
+    let config = write_config(
+        "line",
+        "[services.chat]\nport = 1\nidle_timeout = \"soon\"\n",
+    );
+    let stderr = refused(&["--config", config.to_str().unwrap(), "run"]);
+
+    assert!(stderr.contains("line 3"));
+    assert!(stderr.contains("`soon` is not a duration"));
+}
+
+#[test]
+fn refuses_unknown_keys() {
+    let config = write_config("unknown", "[services.chat]\nport = 1\nwelcom = \"hi\"\n");
+    let stderr = refused(&["--config", config.to_str().unwrap(), "run"]);
+
+    assert!(stderr.contains("unknown field `welcom`"));
+}
+
+#[test]
+fn refuses_unknown_transforms() {
+    let config = write_config(
+        "transform",
+        "[services.echo]\nport = 1\ntransform = \"rot13\"\n",
+    );
+    let stderr = refused(&["--config", config.to_str().unwrap(), "run"]);
+
+    assert!(stderr.contains("unknown transform `rot13`"));
+}
+
+#[test]
+fn refuses_services_listening_on_the_same_address() {
+    let stderr = refused(&["run", "echo=7", "prime=7", "--listen", "127.0.0.1"]);
+
+    assert!(stderr.contains("tcp://127.0.0.1:7"));
+    assert!(stderr.contains("the address is listened on more than once"));
+}
+
+#[tokio::test]
+async fn shares_a_port_between_tcp_and_udp() {
+    let port = free_port();
+    let server = Binary::spawn(&[
+        "run",
+        &format!("echo={port}"),
+        &format!("kv={port}"),
+        "--listen",
+        "127.0.0.1",
+    ]);
+
+    let mut client = server.connect(port).await;
+    client.send("hello\n").await;
+    client.expect("hello\n").await;
+}
+
+#[tokio::test]
+async fn takes_diagnostics_per_listen_address() {
+    let (plain, upper) = (free_port(), free_port());
+    let config = write_config(
+        "listen",
+        &format!(
+            "[services.echo]\n\
+             port = {plain}\n\
+             listen = [\"127.0.0.1\", {{ addr = \"127.0.0.1:{upper}\", transform = \"uppercase\" }}]\n"
+        ),
+    );
+    let server = Binary::spawn(&["run", "--config", config.to_str().unwrap()]);
+
+    let mut client = server.connect(plain).await;
+    client.send("hello\n").await;
+    client.expect("hello\n").await;
+
+    let mut client = server.connect(upper).await;
+    client.send("hello\n").await;
+    client.expect("HELLO\n").await;
+}
+
+#[test]
+fn refuses_diagnostics_of_another_service() {
+    let config = write_config(
+        "listen-chat",
+        "[services.chat]\nport = 1\nlisten = [{ addr = \"127.0.0.1\", delay = \"1s\" }]\n",
+    );
+    let stderr = refused(&["--config", config.to_str().unwrap(), "run"]);
+
+    assert!(stderr.contains("`listen` 127.0.0.1 sets diagnostics of the echo, not of chat"));
+}
+
+#[tokio::test]
+async fn takes_the_diagnostics_flags_of_the_echo() {
+    let port = free_port();
+    let server = Binary::spawn(&[
+        "echo",
+        "--listen",
+        "127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "--transform",
+        "uppercase",
+    ]);
+
+    let mut client = server.connect(port).await;
+    client.send("hello\n").await;
+    client.expect("HELLO\n").await;
+}
@@ -0,0 +1,334 @@
+//! Smoke test scenarios.
+
+mod common;
+
+use common::{socket_path, start_tcp, start_udp, Client, Datagrams};
+use protocore::cli::{ListenAddr, Listeners};
+use protocore::net::{bind_tcp, bind_unix, bind_unix_datagram, Server};
+use protocore::shutdown::Shutdown;
+use protohacker0::{Diagnostics, Settings, Transform};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixDatagram, UnixStream};
+
+async fn start() -> common::Running {
+    start_with(Settings::default()).await
+}
+
+async fn start_with(settings: Settings) -> common::Running {
+    start_tcp("echo", |server, listeners| async move {
+        protohacker0::serve_with(&server, listeners, settings).await
+    })
+}
+
+/// Send `size` bytes while reading the echo, which has to match.
+async fn transfer(addr: SocketAddr, size: usize) {
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    let (mut read, mut write) = TcpStream::connect(addr).await.unwrap().into_split();
+
+    let sent = data.clone();
+    let sender = tokio::spawn(async move {
+        write.write_all(&sent).await.unwrap();
+        write.shutdown().await.unwrap();
+    });
+
+    let mut echoed = Vec::with_capacity(size);
+    read.read_to_end(&mut echoed).await.unwrap();
+    sender.await.unwrap();
+
+    assert_eq!(echoed.len(), data.len());
+    assert!(echoed == data, "the echo differs from what was sent");
+}
+
+#[tokio::test]
+async fn echoes_every_byte_value() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    let data: Vec<u8> = (0..=255).collect();
+    client.send(&data).await;
+    client.expect(&data).await;
+}
+
+#[tokio::test]
+async fn echoes_until_the_client_finishes() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    client.send("hello, ").await;
+    client.send("world\n").await;
+    client.finish().await;
+
+    client.expect("hello, world\n").await;
+    client.expect_closed().await;
+}
+
+#[tokio::test]
+async fn serves_several_clients_at_once() {
+    let server = start().await;
+
+    let mut clients = Vec::new();
+    for _ in 0..5 {
+        clients.push(Client::connect(server.addr).await);
+    }
+
+    for (i, client) in clients.iter_mut().enumerate().rev() {
+        client.send(format!("client {i}\n")).await;
+    }
+
+    for (i, client) in clients.iter_mut().enumerate() {
+        client.expect(format!("client {i}\n")).await;
+    }
+}
+
+#[tokio::test]
+async fn small_buffers_echo_large_transfers() {
+    let server = start_with(Settings {
+        buffer_size: 7,
+        ..Settings::default()
+    })
+    .await;
+
+    transfer(server.addr, 1 << 20).await;
+}
+
+#[tokio::test]
+async fn splice_echoes_large_transfers() {
+    let server = start_with(Settings {
+        splice: true,
+        ..Settings::default()
+    })
+    .await;
+
+    transfer(server.addr, 16 << 20).await;
+
+    let mut client = Client::connect(server.addr).await;
+    let data: Vec<u8> = (0..=255).collect();
+    client.send(&data).await;
+    client.expect(&data).await;
+}
+
+/// Send `data`, shut down our side, and only then read the echo.
+async fn half_close(addr: SocketAddr, data: &[u8]) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(data).await.unwrap();
+    stream.shutdown().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let mut echoed = Vec::new();
+    stream.read_to_end(&mut echoed).await.unwrap();
+    assert!(echoed == data, "the echo differs from what was sent");
+}
+
+#[tokio::test]
+async fn echoes_everything_sent_before_a_half_close() {
+    let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+
+    for splice in [false, true] {
+        let server = start_with(Settings {
+            splice,
+            ..Settings::default()
+        })
+        .await;
+        half_close(server.addr, &data).await;
+    }
+}
+
+#[tokio::test]
+async fn outlives_clients_that_stop_reading() {
+    let server = start().await;
+
+    // Fill both directions until the server blocks on its echo, then leave
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    let data = vec![0; 1 << 20];
+    let _ = tokio::time::timeout(std::time::Duration::from_millis(200), async {
+        loop {
+            stream.write_all(&data).await.unwrap();
+        }
+    })
+    .await;
+    drop(stream);
+
+    let mut client = Client::connect(server.addr).await;
+    client.send("still there?\n").await;
+    client.expect("still there?\n").await;
+}
+
+#[tokio::test]
+async fn echoes_datagrams_over_udp() {
+    let server = start_udp("echo", |server, sockets| async move {
+        let listeners = Listeners {
+            udp: sockets,
+            ..Listeners::default()
+        };
+        protohacker0::serve_all(&server, listeners, Settings::default()).await
+    });
+    let client = Datagrams::connect(server.addr).await;
+
+    client.send("hello").await;
+    client.expect("hello").await;
+    client.send("").await;
+    client.expect("").await;
+}
+
+#[tokio::test]
+async fn echoes_over_unix_sockets() {
+    let stream_path = socket_path("echo-stream");
+    let datagram_path = socket_path("echo-datagram");
+    let listeners = Listeners {
+        unix: vec![bind_unix(&stream_path).unwrap()],
+        unix_datagram: vec![bind_unix_datagram(&datagram_path).unwrap()],
+        ..Listeners::default()
+    };
+    let shutdown = Shutdown::new();
+    let server = Server::new("echo", &shutdown);
+    let serving = tokio::spawn(async move {
+        protohacker0::serve_all(&server, listeners, Settings::default()).await
+    });
+
+    let mut stream = UnixStream::connect(&stream_path).await.unwrap();
+    stream.write_all(b"hello, unix\n").await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut echoed = String::new();
+    stream.read_to_string(&mut echoed).await.unwrap();
+    assert_eq!(echoed, "hello, unix\n");
+
+    // The echo can only come back to a client bound to a path of its own
+    let client_path = socket_path("echo-datagram-client");
+    let _ = std::fs::remove_file(&client_path);
+    let client = UnixDatagram::bind(&client_path).unwrap();
+    client.send_to(b"ping", &datagram_path).await.unwrap();
+    let mut buff = [0; 16];
+    let n = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buff))
+        .await
+        .expect("timed out waiting for the echo")
+        .unwrap();
+    assert_eq!(&buff[..n], b"ping");
+
+    shutdown.trigger();
+    serving.await.unwrap().unwrap();
+    assert!(!stream_path.exists() && !datagram_path.exists());
+}
+
+/// Start an echo with `transform`, and send `data` over a connection closed
+/// right after, returning everything echoed.
+async fn transformed(transform: Transform, data: &[u8]) -> String {
+    let server = start_with(Settings {
+        transform,
+        ..Settings::default()
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(data).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut echoed = String::new();
+    stream.read_to_string(&mut echoed).await.unwrap();
+    echoed
+}
+
+#[tokio::test]
+async fn transforms_the_echo() {
+    assert_eq!(
+        transformed(Transform::Uppercase, b"Hello, world\n").await,
+        "HELLO, WORLD\n"
+    );
+    // The last line is reversed once the client is done, whole or not
+    assert_eq!(
+        transformed(Transform::ReverseLine, "abc\nhé!\nxyz".as_bytes()).await,
+        "cba\n!éh\nzyx"
+    );
+    assert_eq!(
+        transformed(Transform::HexDump, b"0123456789abcdef\x00hi").await,
+        "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n\
+         00000010  00 68 69                                          |.hi|\n"
+    );
+}
+
+#[tokio::test]
+async fn transforms_datagrams_one_by_one() {
+    let server = start_udp("echo", |server, sockets| async move {
+        let listeners = Listeners {
+            udp: sockets,
+            ..Listeners::default()
+        };
+        let settings = Settings {
+            transform: Transform::ReverseLine,
+            ..Settings::default()
+        };
+        protohacker0::serve_all(&server, listeners, settings).await
+    });
+    let client = Datagrams::connect(server.addr).await;
+
+    client.send("abc").await;
+    client.expect("cba").await;
+    client.send("de\nf").await;
+    client.expect("ed\nf").await;
+}
+
+#[tokio::test]
+async fn gives_each_listen_address_its_diagnostics() {
+    let bind = || bind_tcp("127.0.0.1:0".parse().unwrap(), true).unwrap();
+    let (plain, upper) = (bind(), bind());
+    let (plain_addr, upper_addr) = (plain.local_addr().unwrap(), upper.local_addr().unwrap());
+
+    let listen = ListenAddr::Socket(None, upper_addr);
+    let settings = Settings {
+        listeners: vec![(
+            listen.clone(),
+            Diagnostics {
+                transform: Some(Transform::Uppercase),
+                ..Diagnostics::default()
+            },
+        )],
+        ..Settings::default()
+    };
+    let tcp = |listener| Listeners {
+        tcp: vec![listener],
+        ..Listeners::default()
+    };
+    let listeners = vec![(None, tcp(plain)), (Some(listen), tcp(upper))];
+    let shutdown = Shutdown::new();
+    let server = Server::new("echo", &shutdown);
+    tokio::spawn(async move { protohacker0::serve_listeners(&server, listeners, settings).await });
+
+    let mut client = Client::connect(plain_addr).await;
+    client.send("hello\n").await;
+    client.expect("hello\n").await;
+
+    let mut client = Client::connect(upper_addr).await;
+    client.send("hello\n").await;
+    client.expect("HELLO\n").await;
+    shutdown.trigger();
+}
+
+#[tokio::test]
+async fn delays_the_echo() {
+    let server = start_with(Settings {
+        delay: Some(Duration::from_millis(200)),
+        ..Settings::default()
+    })
+    .await;
+    let mut client = Client::connect(server.addr).await;
+
+    let started = Instant::now();
+    client.send("late\n").await;
+    client.expect("late\n").await;
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn throttles_and_fragments_the_echo() {
+    let server = start_with(Settings {
+        throttle: Some(20_000),
+        fragment: Some(7),
+        ..Settings::default()
+    })
+    .await;
+
+    // 10 kB at 20 kB/s, sent 2 kB every 100ms
+    let started = Instant::now();
+    transfer(server.addr, 10_000).await;
+    assert!(started.elapsed() >= Duration::from_millis(400));
+}
@@ -0,0 +1,94 @@
+//! Unusual database program scenarios.
+
+mod common;
+
+use common::{start_udp, Datagrams};
+
+async fn start() -> common::Running {
+    start_udp("kv", |server, sockets| async move {
+        protohacker4::serve(&server, sockets).await
+    })
+}
+
+#[tokio::test]
+async fn retrieves_what_was_inserted() {
+    let server = start().await;
+    let client = Datagrams::connect(server.addr).await;
+
+    client.send("foo=bar").await;
+    client.send("foo").await;
+    client.expect("foo=bar").await;
+
+    client.send("foo=baz").await;
+    client.send("foo").await;
+    client.expect("foo=baz").await;
+}
+
+#[tokio::test]
+async fn values_keep_everything_after_the_first_equal_sign() {
+    let server = start().await;
+    let client = Datagrams::connect(server.addr).await;
+
+    for (insert, key, reply) in [
+        ("foo=bar=baz", "foo", "foo=bar=baz"),
+        ("foo=", "foo", "foo="),
+        ("foo===", "foo", "foo==="),
+        ("=foo", "", "=foo"),
+    ] {
+        client.send(insert).await;
+        client.send(key).await;
+        client.expect(reply).await;
+    }
+}
+
+#[tokio::test]
+async fn unknown_keys_are_empty() {
+    let server = start().await;
+    let client = Datagrams::connect(server.addr).await;
+
+    client.send("missing").await;
+    client.expect("missing=").await;
+}
+
+#[tokio::test]
+async fn the_version_cannot_be_changed() {
+    let server = start().await;
+    let client = Datagrams::connect(server.addr).await;
+
+    client.send("version").await;
+    client.expect("version=1.0").await;
+
+    client.send("version=2.0").await;
+    client.send("version").await;
+    client.expect("version=1.0").await;
+}
+
+#[tokio::test]
+async fn clients_share_the_store() {
+    let server = start().await;
+    let writer = Datagrams::connect(server.addr).await;
+    let reader = Datagrams::connect(server.addr).await;
+
+    writer.send("shared=yes").await;
+    // Datagrams from different sockets are not ordered, sync on a reply first
+    writer.send("shared").await;
+    writer.expect("shared=yes").await;
+
+    reader.send("shared").await;
+    reader.expect("shared=yes").await;
+}
+
+#[tokio::test]
+async fn replies_are_cut_to_the_configured_size() {
+    let settings = protohacker4::Settings {
+        max_datagram_size: 8,
+    };
+    let server = start_udp("kv", |server, sockets| async move {
+        protohacker4::serve_with(&server, sockets, settings).await
+    });
+    let client = Datagrams::connect(server.addr).await;
+
+    client.send("foo=barbaz").await;
+    client.send("foo").await;
+    client.expect("foo=barb").await;
+}
@@ -0,0 +1,101 @@
+//! Means to an end scenarios.
+
+mod common;
+
+use common::{start_tcp, Client};
+
+async fn start() -> common::Running {
+    start_tcp("means", |server, listeners| async move {
+        protohacker2::serve(&server, listeners).await
+    })
+}
+
+fn message(kind: u8, first: i32, second: i32) -> Vec<u8> {
+    [&[kind][..], &first.to_be_bytes(), &second.to_be_bytes()].concat()
+}
+
+fn insert(timestamp: i32, price: i32) -> Vec<u8> {
+    message(b'I', timestamp, price)
+}
+
+fn query(min_time: i32, max_time: i32) -> Vec<u8> {
+    message(b'Q', min_time, max_time)
+}
+
+#[tokio::test]
+async fn example_session() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    client.send(insert(12345, 101)).await;
+    client.send(insert(12346, 102)).await;
+    client.send(insert(12347, 100)).await;
+    client.send(insert(40960, 5)).await;
+    client.send(query(12288, 16384)).await;
+
+    client.expect(101_i32.to_be_bytes()).await;
+}
+
+#[tokio::test]
+async fn messages_can_be_split_anywhere() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    let messages = [insert(1, -10), insert(2, -20), query(0, 2)].concat();
+    for byte in messages {
+        client.send([byte]).await;
+    }
+
+    client.expect((-15_i32).to_be_bytes()).await;
+}
+
+#[tokio::test]
+async fn empty_or_reversed_ranges_average_to_zero() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    client.send(insert(100, 42)).await;
+    client.send(query(200, 300)).await;
+    client.send(query(100, 0)).await;
+
+    client
+        .expect([0_i32.to_be_bytes(), 0_i32.to_be_bytes()].concat())
+        .await;
+}
+
+#[tokio::test]
+async fn sessions_do_not_share_prices() {
+    let server = start().await;
+    let mut first = Client::connect(server.addr).await;
+    let mut second = Client::connect(server.addr).await;
+
+    first.send(insert(1, 1000)).await;
+    second.send(insert(1, 10)).await;
+
+    first.send(query(0, 1)).await;
+    second.send(query(0, 1)).await;
+
+    first.expect(1000_i32.to_be_bytes()).await;
+    second.expect(10_i32.to_be_bytes()).await;
+}
+
+#[tokio::test]
+async fn averages_do_not_overflow() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    client.send(insert(1, i32::MAX)).await;
+    client.send(insert(2, i32::MAX)).await;
+    client.send(query(i32::MIN, i32::MAX)).await;
+
+    client.expect(i32::MAX.to_be_bytes()).await;
+}
+
+#[tokio::test]
+async fn disconnects_on_an_unknown_command() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    client.send(message(b'X', 1, 2)).await;
+    client.expect_closed().await;
+}
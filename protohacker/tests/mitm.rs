@@ -0,0 +1,101 @@
+//! Mob in the middle scenarios, against a local budget chat standing in for
+//! Tony's upstream server.
+
+mod common;
+
+use common::{start_tcp, Client, Running};
+use protohacker5::Upstream;
+
+const WELCOME: &str = "Welcome to budgetchat! What shall I call you?\n";
+const TONY: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
+
+/// Start the upstream chat and a proxy in front of it.
+async fn start() -> (Running, Running) {
+    let chat = start_tcp("chat", |server, listeners| async move {
+        protohacker3::serve(&server, listeners).await
+    });
+
+    let upstream = Upstream {
+        host: chat.addr.ip().to_string(),
+        port: chat.addr.port(),
+        ..Upstream::default()
+    };
+    let proxy = start_tcp("mitm", |server, listeners| async move {
+        protohacker5::serve(&server, listeners, upstream).await
+    });
+
+    (chat, proxy)
+}
+
+async fn join(addr: std::net::SocketAddr, name: &str, room: &str) -> Client {
+    let mut client = Client::connect(addr).await;
+    client.expect(WELCOME).await;
+    client.send(format!("{name}\n")).await;
+    client
+        .expect(format!("*Welcome. Users in room: {room}\n"))
+        .await;
+    client
+}
+
+#[tokio::test]
+async fn rewrites_addresses_both_ways() {
+    let (chat, proxy) = start().await;
+
+    let mut victim = join(chat.addr, "victim", "").await;
+    let mut mallory = join(proxy.addr, "mallory", ", victim").await;
+    victim.expect("* mallory has joined the room\n").await;
+
+    mallory
+        .send("Send it to 7F1u3wSD5RbOHQmupo9nx4TnhQ\n")
+        .await;
+    victim
+        .expect(format!("[mallory] Send it to {TONY}\n"))
+        .await;
+
+    victim
+        .send("7iKDZEwPZSqIvDnHvVN2r0hUWXD5rHX please\n")
+        .await;
+    mallory.expect(format!("[victim] {TONY} please\n")).await;
+
+    victim
+        .send("Two: 7LOrwbDlS8NujgjddyogWgIM93MV5N2VR 7adNeSwJkMakpEcln9HEtthSRtxdmEHOT8T\n")
+        .await;
+    mallory
+        .expect(format!("[victim] Two: {TONY} {TONY}\n"))
+        .await;
+}
+
+#[tokio::test]
+async fn leaves_lookalikes_alone() {
+    let (chat, proxy) = start().await;
+
+    let mut victim = join(chat.addr, "victim", "").await;
+    let mut mallory = join(proxy.addr, "mallory", ", victim").await;
+    victim.expect("* mallory has joined the room\n").await;
+
+    for message in [
+        // too short
+        "7F1u3wSD5RbOHQmupo9nx4",
+        // too long
+        "7F1u3wSD5RbOHQmupo9nx4TnhQ7F1u3wSD5RbOHQmupo9nx4TnhQ",
+        // part of a longer word
+        "This is a product ID, not a Boguscoin: 7F1u3wSD5RbOHQmupo9nx4TnhQ-1234",
+        // not starting with a 7
+        "8F1u3wSD5RbOHQmupo9nx4TnhQ",
+    ] {
+        mallory.send(format!("{message}\n")).await;
+        victim.expect(format!("[mallory] {message}\n")).await;
+    }
+}
+
+#[tokio::test]
+async fn disconnects_with_the_upstream() {
+    let (_chat, proxy) = start().await;
+
+    let mut client = Client::connect(proxy.addr).await;
+    client.expect(WELCOME).await;
+
+    // An invalid name makes the upstream hang up, the proxy follows
+    client.send("not valid\n").await;
+    client.expect_closed().await;
+}
@@ -0,0 +1,89 @@
+//! Prime time scenarios.
+
+mod common;
+
+use common::{start_tcp, Client};
+
+const PRIME: &str = "{\"method\":\"isPrime\",\"prime\":true}\n";
+const COMPOSITE: &str = "{\"method\":\"isPrime\",\"prime\":false}\n";
+const MALFORMED: &str = "{\"method\":\"invalid\",\"prime\":false}\n";
+
+async fn start() -> common::Running {
+    start_tcp("prime", |server, listeners| async move {
+        protohacker1::serve(&server, listeners).await
+    })
+}
+
+fn request(number: &str) -> String {
+    format!("{{\"method\":\"isPrime\",\"number\":{number}}}\n")
+}
+
+#[tokio::test]
+async fn tells_primes_apart() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    for (number, reply) in [
+        ("2", PRIME),
+        ("7", PRIME),
+        ("7919", PRIME),
+        ("1", COMPOSITE),
+        ("0", COMPOSITE),
+        ("123", COMPOSITE),
+        ("-7", COMPOSITE),
+        ("7.5", COMPOSITE),
+        ("7.0", PRIME),
+        // The largest prime a JSON number holds exactly
+        ("9007199254740881", PRIME),
+        ("9007199254740883", COMPOSITE),
+    ] {
+        client.send(request(number)).await;
+        client.expect(reply).await;
+    }
+}
+
+#[tokio::test]
+async fn ignores_extra_fields() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    client
+        .send("{\"number\":13,\"extra\":[1,2],\"method\":\"isPrime\"}\n")
+        .await;
+    client.expect(PRIME).await;
+}
+
+#[tokio::test]
+async fn answers_requests_in_order_however_they_are_split() {
+    let server = start().await;
+    let mut client = Client::connect(server.addr).await;
+
+    let requests = [request("3"), request("4"), request("5")].concat();
+    let (first, second) = requests.split_at(20);
+
+    client.send(first).await;
+    client.send(second).await;
+
+    client.expect([PRIME, COMPOSITE, PRIME].concat()).await;
+}
+
+#[tokio::test]
+async fn disconnects_after_a_malformed_request() {
+    let server = start().await;
+
+    for malformed in [
+        "{\"method\":\"isPrime\"}\n",
+        "{\"method\":\"isComposite\",\"number\":7}\n",
+        "{\"method\":\"isPrime\",\"number\":\"7\"}\n",
+        "not json\n",
+    ] {
+        let mut client = Client::connect(server.addr).await;
+
+        client.send(request("7")).await;
+        client.expect(PRIME).await;
+
+        client.send(malformed).await;
+        client.expect(MALFORMED).await;
+        client.expect_closed().await;
+    }
+}
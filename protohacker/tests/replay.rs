@@ -0,0 +1,133 @@
+//! Recording sessions with `--record` and replaying them with `protohacker
+//! replay`.
+
+mod common;
+
+use common::{free_port, temp_path, Binary, TempPath};
+use protocore::record::{read_events, Event, EventKind, Recorder};
+use std::path::Path;
+use std::process::Output;
+use std::time::{Duration, Instant};
+
+/// Wait for the recording at `path` to hold an event matching `done`,
+/// returning every event so far.
+fn events_until(path: &Path, done: impl Fn(&[Event]) -> bool) -> Vec<Event> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    loop {
+        let events = read_events(path).unwrap_or_default();
+        if done(&events) {
+            return events;
+        }
+        assert!(Instant::now() < deadline, "the recording is incomplete");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn count(events: &[Event], kind: EventKind) -> usize {
+    events.iter().filter(|event| event.kind == kind).count()
+}
+
+/// Replay the recording at `path` against `port` of the loopback.
+fn replay(path: &Path, port: u16) -> (Option<i32>, String) {
+    let target = format!("127.0.0.1:{port}");
+    let Output { status, stdout, .. } = Binary::command()
+        .args(["replay", path.to_str().unwrap(), "--target", &target])
+        .output()
+        .unwrap();
+    (status.code(), String::from_utf8_lossy(&stdout).into_owned())
+}
+
+#[test]
+fn round_trips_every_byte_value() {
+    let path = temp_path("bytes.jsonl");
+    let data: Vec<u8> = (0..=255).collect();
+
+    let recorder = Recorder::create(&path).unwrap();
+    recorder.datagram("kv", "127.0.0.1:1".parse().unwrap(), EventKind::In, &data);
+
+    let events = events_until(&path, |events| !events.is_empty());
+    assert_eq!(events[0].data.len(), 2 * data.len());
+    assert_eq!(events[0].bytes().unwrap(), data);
+}
+
+#[test]
+fn refuses_bytes_that_are_not_hex() {
+    let event = |data: &str| Event {
+        at: 0,
+        service: "echo".to_string(),
+        conn: Some(1),
+        peer: "127.0.0.1:1".parse().unwrap(),
+        kind: EventKind::In,
+        data: data.to_string(),
+    };
+
+    assert_eq!(event("00ff7f").bytes().unwrap(), [0x00, 0xff, 0x7f]);
+    assert!(event("abc").bytes().is_err());
+    assert!(event("zz").bytes().is_err());
+}
+
+/// Record a session of an echo, returning the echo still running, its port
+/// and a copy of the recording: the server keeps recording, the replays
+/// included.
+async fn record_session(name: &str) -> (Binary, u16, TempPath) {
+    let recording = temp_path(&format!("{name}.jsonl"));
+    let port = free_port();
+    let server = Binary::spawn(&[
+        "--record",
+        recording.to_str().unwrap(),
+        "echo",
+        "--listen",
+        "127.0.0.1",
+        "-p",
+        &port.to_string(),
+    ]);
+
+    let mut client = server.connect(port).await;
+    client.send("hello\n").await;
+    client.expect("hello\n").await;
+    drop(client);
+    // Connecting once the server is up opened a connection of its own
+    events_until(&recording, |events| {
+        count(events, EventKind::Out) == 1
+            && count(events, EventKind::Close) == count(events, EventKind::Open)
+    });
+
+    let session = temp_path(&format!("{name}-session.jsonl"));
+    std::fs::copy(&recording, &session).unwrap();
+    (server, port, session)
+}
+
+#[tokio::test]
+async fn replays_a_recorded_session() {
+    let (_server, port, session) = record_session("clean").await;
+
+    let (code, stdout) = replay(&session, port);
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains(": 0 mismatch(es)"), "{stdout}");
+}
+
+#[tokio::test]
+async fn reports_replies_that_changed() {
+    let (_server, _, session) = record_session("changed").await;
+
+    let port = free_port();
+    let config = temp_path("changed.toml");
+    let echo = format!(
+        "[services.echo]\n\
+         port = {port}\n\
+         listen = [\"127.0.0.1\"]\n\
+         transform = \"uppercase\"\n"
+    );
+    std::fs::write(&config, echo).unwrap();
+    let changed = Binary::spawn(&["--config", config.to_str().unwrap(), "run"]);
+    changed.connect(port).await;
+
+    let (code, stdout) = replay(&session, port);
+    assert_eq!(code, Some(1), "{stdout}");
+    assert!(
+        stdout.contains("expected \"hello\\n\", got \"HELLO\\n\""),
+        "{stdout}"
+    );
+    assert!(stdout.contains(": 1 mismatch(es)"), "{stdout}");
+}
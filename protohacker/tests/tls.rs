@@ -0,0 +1,126 @@
+//! The servers and the proxy upstream over TLS, with the self-signed fixture
+//! certificate.
+
+mod common;
+
+use common::{start_tcp, tls_acceptor, Client, CERT};
+use protohacker5::Upstream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn echoes_over_tls() {
+    let server = start_tcp("echo", |server, listeners| async move {
+        let server = server.with_tls(Some(tls_acceptor()));
+        protohacker0::serve(&server, listeners).await
+    });
+    let mut client = Client::connect_tls(server.addr).await;
+
+    client.send("hello, world\n").await;
+    client.finish().await;
+    client.expect("hello, world\n").await;
+    client.expect_closed().await;
+}
+
+#[tokio::test]
+async fn answers_primes_over_tls() {
+    let server = start_tcp("prime", |server, listeners| async move {
+        let server = server.with_tls(Some(tls_acceptor()));
+        protohacker1::serve(&server, listeners).await
+    });
+    let mut client = Client::connect_tls(server.addr).await;
+
+    client.send("{\"method\":\"isPrime\",\"number\":7}\n").await;
+    client
+        .expect("{\"method\":\"isPrime\",\"prime\":true}\n")
+        .await;
+}
+
+#[tokio::test]
+async fn drops_plaintext_clients() {
+    let server = start_tcp("echo", |server, listeners| async move {
+        let server = server.with_tls(Some(tls_acceptor()));
+        protohacker0::serve(&server, listeners).await
+    });
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+
+    stream.write_all(b"hello, world\n").await.unwrap();
+
+    // At most a TLS alert comes back, never the echo
+    let mut received = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received));
+    let _ = read.await.expect("the server kept the connection open");
+    assert!(!received.windows(5).any(|w| w == b"hello"));
+}
+
+#[tokio::test]
+async fn proxies_to_a_tls_upstream() {
+    let chat = start_tcp("chat", |server, listeners| async move {
+        let server = server.with_tls(Some(tls_acceptor()));
+        protohacker3::serve(&server, listeners).await
+    });
+
+    let upstream = Upstream {
+        host: chat.addr.ip().to_string(),
+        port: chat.addr.port(),
+        tls: true,
+        ca: Some(PathBuf::from(CERT)),
+    };
+    let proxy = start_tcp("mitm", |server, listeners| async move {
+        let server = server.with_tls(Some(tls_acceptor()));
+        protohacker5::serve(&server, listeners, upstream).await
+    });
+
+    let mut alice = Client::connect_tls(proxy.addr).await;
+    alice
+        .expect("Welcome to budgetchat! What shall I call you?\n")
+        .await;
+    alice.send("alice\n").await;
+    alice.expect("*Welcome. Users in room: \n").await;
+}
+
+#[tokio::test]
+async fn gives_up_on_a_silent_tls_upstream() {
+    // Connections queue up in the backlog but the handshake is never answered
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = silent.local_addr().unwrap();
+
+    let settings = protohacker5::Settings {
+        upstream_timeout: Duration::from_millis(200),
+        ..protohacker5::Settings::with_upstream(Upstream {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+            tls: true,
+            ca: Some(PathBuf::from(CERT)),
+        })
+    };
+    let proxy = start_tcp("mitm", |server, listeners| async move {
+        protohacker5::serve_with(&server, listeners, settings).await
+    });
+
+    let mut client = Client::connect(proxy.addr).await;
+    client.expect_closed().await;
+}
+
+#[test]
+fn upstream_urls_select_tls() {
+    let upstream: Upstream = "tls://chat.example:16963".parse().unwrap();
+    assert!(upstream.tls);
+    assert_eq!(upstream.host, "chat.example");
+    assert_eq!(upstream.to_string(), "tls://chat.example:16963");
+
+    let upstream: Upstream = "chat.example:16963".parse().unwrap();
+    assert!(!upstream.tls);
+}
+
+#[test]
+fn missing_certificates_are_reported() {
+    let missing = Path::new("/nonexistent/cert.pem");
+    let error = protocore::tls::acceptor(missing, Path::new(common::KEY))
+        .err()
+        .expect("loaded a missing certificate");
+
+    assert!(error.to_string().contains("/nonexistent/cert.pem"));
+}
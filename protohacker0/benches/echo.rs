@@ -0,0 +1,89 @@
+//! Throughput of the echo modes on large transfers over the loopback:
+//! `cargo bench -p protohacker0`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use protocore::net::{bind_tcp, Server};
+use protocore::shutdown::Shutdown;
+use protohacker0::Settings;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+/// Bytes sent, and echoed, per iteration.
+const TRANSFER: usize = 64 << 20;
+
+/// Serve the echo with `settings` on the loopback, in the background.
+fn start(runtime: &Runtime, settings: Settings, shutdown: &Shutdown) -> SocketAddr {
+    let _guard = runtime.enter();
+    let listener = bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), true).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = Server::new("echo", shutdown);
+    runtime.spawn(async move { protohacker0::serve_with(&server, vec![listener], settings).await });
+
+    addr
+}
+
+/// Send `data` while reading the echo back.
+async fn transfer(addr: SocketAddr, data: &'static [u8]) {
+    let (mut read, mut write) = TcpStream::connect(addr).await.unwrap().into_split();
+
+    let sender = tokio::spawn(async move {
+        write.write_all(data).await.unwrap();
+        write.shutdown().await.unwrap();
+    });
+
+    let mut buff = vec![0; 1 << 20];
+    let mut echoed = 0;
+    loop {
+        match read.read(&mut buff).await.unwrap() {
+            0 => break,
+            n => echoed += n,
+        }
+    }
+    sender.await.unwrap();
+    assert_eq!(echoed, data.len());
+}
+
+fn echo(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let shutdown = Shutdown::new();
+    let data: &'static [u8] = vec![0x55; TRANSFER].leak();
+
+    let modes = [
+        // What every connection used before the buffer was made configurable
+        (
+            "copy/128",
+            Settings {
+                buffer_size: 128,
+                ..Settings::default()
+            },
+        ),
+        ("copy/64k", Settings::default()),
+        (
+            "splice/64k",
+            Settings {
+                splice: true,
+                ..Settings::default()
+            },
+        ),
+    ];
+
+    let mut group = c.benchmark_group("echo");
+    group.throughput(Throughput::Bytes(TRANSFER as u64));
+    group.sample_size(10);
+
+    for (name, settings) in modes {
+        let addr = start(&runtime, settings, &shutdown);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.to_async(&runtime).iter(|| transfer(addr, data))
+        });
+    }
+
+    group.finish();
+    shutdown.trigger();
+}
+
+criterion_group!(benches, echo);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+use protocore::blocking::io::send_to_socket;
+use std::io::{ErrorKind, Read};
+use std::net::{Shutdown, TcpStream};
+use tracing::debug;
+
+use crate::client_left;
+
+/// Blocking version of [`crate::handle_echo`].
+pub fn handle_echo(mut stream: TcpStream) -> std::io::Result<()> {
+    // Reads have to wait for the client, whatever the listener handed over
+    stream.set_nonblocking(false)?;
+    let mut buff: Vec<u8> = vec![0; crate::BUFFER_SIZE];
+
+    let finished = loop {
+        let n = match stream.read(&mut buff) {
+            Ok(0) => break stream.shutdown(Shutdown::Write), // EOF
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        };
+
+        if let Err(e) = send_to_socket(&mut stream, &buff[..n]) {
+            break Err(e);
+        }
+    };
+
+    match finished {
+        Err(e) if client_left(&e) => {
+            debug!(error = %e, "the client left before its echo");
+            Ok(())
+        }
+        finished => finished,
+    }
+}
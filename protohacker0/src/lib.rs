@@ -0,0 +1,313 @@
+use clap::Args;
+use protocore::cli::{parse_duration, ListenAddr, Listeners};
+use protocore::net::{Context, Server};
+use protocore::reload::Reloadable;
+use protocore::stream::Stream;
+use std::convert::Infallible;
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::debug;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod shape;
+#[cfg(target_os = "linux")]
+mod splice;
+mod transform;
+
+use shape::Shaper;
+pub use transform::Transform;
+use transform::Transformer;
+
+/// Most bytes read at once, by default.
+pub const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Largest datagram echoed back whole; longer ones are cut to it.
+pub const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// Tunables of the echo server.
+///
+/// Besides the plain echo, the server can play a slow or flaky peer: delay,
+/// throttle, fragment or transform what it sends back. These diagnostics
+/// apply to stream connections; datagrams only get the transform.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Most bytes read at once, and echoed before reading more.
+    pub buffer_size: usize,
+    /// Echo in the kernel with `splice`, the bytes never being copied to the
+    /// server. Only on Linux, for plain TCP connections that are not recorded
+    /// and without diagnostics; the others are copied as usual.
+    pub splice: bool,
+    /// Hold each read this long before echoing it.
+    pub delay: Option<Duration>,
+    /// Most bytes echoed per second, to each connection.
+    pub throttle: Option<u64>,
+    /// Send the echo in writes of 1 to this many bytes, picked at random.
+    pub fragment: Option<usize>,
+    /// What to send back instead of the bytes as they came.
+    pub transform: Transform,
+    /// Diagnostics of single listen addresses, over the ones above. Only
+    /// [`serve_listeners`] tells the listen addresses apart.
+    pub listeners: Vec<(ListenAddr, Diagnostics)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            buffer_size: BUFFER_SIZE,
+            splice: false,
+            delay: None,
+            throttle: None,
+            fragment: None,
+            transform: Transform::None,
+            listeners: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Whether the echo is anything but the bytes sent back as they came.
+    pub fn has_diagnostics(&self) -> bool {
+        self.delay.is_some()
+            || self.throttle.is_some()
+            || self.fragment.is_some()
+            || self.transform != Transform::None
+    }
+
+    /// These settings, with the diagnostics that are set in `diagnostics`
+    /// replacing theirs.
+    pub fn with_diagnostics(&self, diagnostics: &Diagnostics) -> Settings {
+        Settings {
+            delay: diagnostics.delay.or(self.delay),
+            throttle: diagnostics.throttle.or(self.throttle),
+            fragment: diagnostics.fragment.or(self.fragment),
+            transform: diagnostics.transform.unwrap_or(self.transform),
+            ..self.clone()
+        }
+    }
+
+    /// The settings of the connections accepted for the listen address
+    /// `addr`, or for the default one when `None`.
+    fn listening_on(self: Arc<Settings>, addr: Option<&ListenAddr>) -> Arc<Settings> {
+        let diagnostics = self
+            .listeners
+            .iter()
+            .find(|(listen, _)| Some(listen) == addr);
+
+        match diagnostics {
+            Some((_, diagnostics)) => Arc::new(self.with_diagnostics(diagnostics)),
+            None => self,
+        }
+    }
+}
+
+/// The diagnostics of a listener, or of the whole server; those left unset
+/// are taken from the settings they are applied over.
+#[derive(Args, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// Hold each read this long before echoing it, e.g. `200ms`
+    #[arg(long, env = "PROTOHACKER_DELAY", value_parser = parse_duration)]
+    pub delay: Option<Duration>,
+
+    /// Echo at most this many bytes per second to each client
+    #[arg(
+        long,
+        env = "PROTOHACKER_THROTTLE",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub throttle: Option<u64>,
+
+    /// Send the echo in writes of 1 to this many bytes, picked at random
+    #[arg(
+        long,
+        env = "PROTOHACKER_FRAGMENT",
+        value_name = "BYTES",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=16 * 1024 * 1024)
+    )]
+    pub fragment: Option<usize>,
+
+    /// What to send back instead of the bytes as they came. Datagrams are transformed one
+    /// by one
+    #[arg(long, env = "PROTOHACKER_TRANSFORM", value_enum)]
+    pub transform: Option<Transform>,
+}
+
+/// Whether a failed read or write only means the client went away, e.g.
+/// closed the connection without reading its echo.
+pub fn client_left(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
+}
+
+/// Echo everything the client sends until it shuts down its side of the
+/// connection, then shut down ours once the echo is out: a client that
+/// half-closes still gets every byte back, followed by an EOF.
+pub async fn handle_echo(stream: Stream, context: Context) -> std::io::Result<()> {
+    handle_echo_with(stream, context, &Settings::default()).await
+}
+
+/// Same as [`handle_echo`], with other settings than the defaults.
+pub async fn handle_echo_with(
+    mut stream: Stream,
+    context: Context,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    let echoed = echo(&mut stream, &context, settings).await;
+    let finished = match echoed {
+        Ok(()) => stream.shutdown().await,
+        Err(e) => Err(e),
+    };
+
+    match finished {
+        Err(e) if client_left(&e) => {
+            debug!(error = %e, "the client left before its echo");
+            Ok(())
+        }
+        finished => finished,
+    }
+}
+
+/// Echo until the client is done sending.
+async fn echo(stream: &mut Stream, context: &Context, settings: &Settings) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    if settings.splice && !settings.has_diagnostics() {
+        if let Some(tcp) = stream.as_plain_tcp() {
+            return splice::echo(stream, tcp, context, settings.buffer_size).await;
+        }
+    }
+
+    copy(stream, context, settings).await
+}
+
+/// Echo through a buffer of the server, reused for the whole connection.
+async fn copy(stream: &mut Stream, context: &Context, settings: &Settings) -> std::io::Result<()> {
+    let mut buff: Vec<u8> = vec![0; settings.buffer_size];
+    let mut transformer = Transformer::new(settings.transform, settings.buffer_size);
+    let mut transformed = Vec::new();
+    let mut shaper = Shaper::new(settings);
+
+    if settings.fragment.is_some() {
+        // Or the fragments may be coalesced again on the way
+        stream.set_nodelay(true)?;
+    }
+
+    loop {
+        // Echo has no messages to speak of, only the idle timeout applies
+        let n = context
+            .timeouts()
+            .limit(None, stream.read(&mut buff))
+            .await?;
+
+        if n == 0 {
+            // EOF, past what the transform held back
+            transformed.clear();
+            transformer.finish(&mut transformed);
+            return shaper.send(stream, &transformed).await;
+        }
+
+        let _request = context.start_request();
+        if settings.transform == Transform::None {
+            shaper.send(stream, &buff[..n]).await?;
+        } else {
+            transformed.clear();
+            transformer.feed(&buff[..n], &mut transformed);
+            shaper.send(stream, &transformed).await?;
+        }
+    }
+}
+
+/// Serve the echo on every listener until the server shuts down.
+pub async fn serve(server: &Server, listeners: Vec<TcpListener>) -> std::io::Result<()> {
+    serve_with(server, listeners, Settings::default()).await
+}
+
+/// Same as [`serve`], with other settings than the defaults. Settings given as
+/// a [`Reloadable`] apply to the connections opened after they change.
+pub async fn serve_with(
+    server: &Server,
+    listeners: Vec<TcpListener>,
+    settings: impl Into<Reloadable<Settings>>,
+) -> std::io::Result<()> {
+    let settings = settings.into();
+
+    server
+        .serve_tcp(listeners, move |stream, context| {
+            let settings = settings.get();
+            async move { handle_echo_with(stream, context, &settings).await }
+        })
+        .await
+}
+
+/// Same as [`serve_with`], over every kind of socket bound: TCP and Unix
+/// streams are echoed as above, each UDP or Unix datagram is sent back whole,
+/// transformed if the settings say so.
+pub async fn serve_all(
+    server: &Server,
+    listeners: Listeners,
+    settings: impl Into<Reloadable<Settings>>,
+) -> std::io::Result<()> {
+    serve_listening(server, None, listeners, settings.into()).await
+}
+
+/// Same as [`serve_all`], for the sockets bound one listen address at a
+/// time: each gets the diagnostics its address has in the settings, `None`
+/// standing for the default address.
+pub async fn serve_listeners(
+    server: &Server,
+    listeners: Vec<(Option<ListenAddr>, Listeners)>,
+    settings: impl Into<Reloadable<Settings>>,
+) -> std::io::Result<()> {
+    let settings = settings.into();
+    let serving = listeners
+        .into_iter()
+        .map(|(addr, listeners)| serve_listening(server, addr, listeners, settings.clone()));
+
+    futures::future::try_join_all(serving).await?;
+    Ok(())
+}
+
+/// Serve the sockets bound for `addr`.
+async fn serve_listening(
+    server: &Server,
+    addr: Option<ListenAddr>,
+    listeners: Listeners,
+    settings: Reloadable<Settings>,
+) -> std::io::Result<()> {
+    let addr = Arc::new(addr);
+    let settings = move || settings.get().listening_on(addr.as_ref().as_ref());
+    let tcp_settings = settings.clone();
+    let unix_settings = settings.clone();
+    let udp_settings = settings.clone();
+    let unix_datagram_settings = settings;
+
+    let handle = |settings: Arc<Settings>, stream: Stream, context: Context| async move {
+        handle_echo_with(stream, context, &settings).await
+    };
+    let echo = |settings: Arc<Settings>, datagram: &[u8]| {
+        Ok::<_, Infallible>(Some(settings.transform.apply(datagram)))
+    };
+    tokio::try_join!(
+        server.serve_tcp(listeners.tcp, move |stream, context| {
+            handle(tcp_settings(), stream, context)
+        }),
+        server.serve_unix(listeners.unix, move |stream, context| {
+            handle(unix_settings(), stream, context)
+        }),
+        server.serve_udp(listeners.udp, MAX_DATAGRAM_SIZE, move |datagram, _| {
+            echo(udp_settings(), datagram)
+        }),
+        server.serve_unix_datagram(
+            listeners.unix_datagram,
+            MAX_DATAGRAM_SIZE,
+            move |datagram| echo(unix_datagram_settings(), datagram)
+        ),
+    )?;
+
+    Ok(())
+}
@@ -1,38 +1,70 @@
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::thread;
-
-fn echo_back(stream: &mut TcpStream, buff: &[u8], n: usize) {
-    let mut s: usize = 0;
-    while s != n {
-        s += stream
-            .write(&buff[s..n])
-            .expect("Server does not want to receive our message");
-    }
-}
+use clap::Parser;
+use protocore::activation::Activation;
+use protocore::cli::{IpProtocol, Listeners, RuntimeArgs, ServerArgs};
+use protocore::net::Server;
+use protocore::shutdown::Shutdown;
+use protohacker0::{Diagnostics, Settings};
 
-fn handle_echo(mut stream: TcpStream) {
-    println!("Handling connection");
-    loop {
-        let mut buff: Vec<u8> = vec![0; 128];
+/// Smoke test: echo back everything the clients send.
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    server: ServerArgs,
 
-        let read = stream.read(&mut buff);
+    #[command(flatten)]
+    runtime: RuntimeArgs,
 
-        match read {
-            Ok(0) => break, // EOF
-            Ok(n) => echo_back(&mut stream, &buff, n),
-            _ => break, // Unhandled error
-        };
-    }
+    /// Most bytes read at once, and echoed before reading more
+    #[arg(
+        long,
+        env = "PROTOHACKER_BUFFER_SIZE",
+        default_value_t = protohacker0::BUFFER_SIZE as u32,
+        value_parser = clap::value_parser!(u32).range(1..=16 * 1024 * 1024)
+    )]
+    buffer_size: u32,
+
+    /// Echo with splice(2), without copying the bytes to the server. Linux only, and only
+    /// for plain TCP connections that are not recorded, without the diagnostics below
+    #[arg(long, env = "PROTOHACKER_SPLICE")]
+    splice: bool,
+
+    #[command(flatten)]
+    diagnostics: Diagnostics,
 }
 
 fn main() -> std::io::Result<()> {
-    println!("Starting listener");
-    let listener = TcpListener::bind("0.0.0.0:80")?;
+    let cli = Cli::parse();
+    protocore::logging::init(cli.runtime.log_format);
+    // Taken before the runtime starts its threads, as it edits the environment
+    let mut activation = Activation::from_env();
+
+    let listeners = cli.server.listen.bind(IpProtocol::Tcp, &mut activation)?;
+    tokio::runtime::Runtime::new()?.block_on(serve(cli, listeners, activation))
+}
+
+async fn serve(cli: Cli, listeners: Listeners, activation: Activation) -> std::io::Result<()> {
+    let shutdown = Shutdown::on_signal()?;
+
+    if let Some(addr) = cli.runtime.metrics {
+        protocore::metrics::spawn_endpoint(addr, &shutdown)?;
+    }
+    if let Some(path) = &cli.runtime.admin {
+        protocore::admin::spawn_socket(path, &shutdown)?;
+    }
 
-    for stream in listener.incoming().flatten() {
-        thread::spawn(move || handle_echo(stream));
+    let server = Server::new("echo", &shutdown)
+        .with_recorder(cli.runtime.recorder()?)
+        .with_limits(cli.server.limits)
+        .with_tls(cli.server.tls.acceptor()?);
+    let settings = Settings {
+        buffer_size: cli.buffer_size as usize,
+        splice: cli.splice,
+        ..Settings::default()
     }
+    .with_diagnostics(&cli.diagnostics);
+    cli.runtime.drop_privileges(&activation)?;
+    protohacker0::serve_all(&server, listeners, settings).await?;
+    shutdown.drain(cli.runtime.grace_period).await;
 
     Ok(())
 }
@@ -0,0 +1,79 @@
+//! Slowing down and splitting the echo, to test clients against slow or
+//! flaky peers.
+
+use protocore::io::send_to_socket;
+use protocore::stream::Stream;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::Settings;
+
+/// Shortest stretch of time a throttled write covers, so a low rate is kept
+/// to by waiting between small writes rather than after a large one.
+const THROTTLE_STEP: Duration = Duration::from_millis(100);
+
+/// Finest sleep of the timer.
+const TIMER_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Writes the echo of a connection, delayed, throttled and fragmented as the
+/// settings ask.
+pub(crate) struct Shaper {
+    delay: Option<Duration>,
+    /// Bytes per second, and when the next byte may go out.
+    throttle: Option<(u64, Instant)>,
+    fragment: Option<(usize, StdRng)>,
+}
+
+impl Shaper {
+    pub(crate) fn new(settings: &Settings) -> Shaper {
+        Shaper {
+            delay: settings.delay,
+            throttle: settings.throttle.map(|rate| (rate, Instant::now())),
+            fragment: settings.fragment.map(|max| (max, StdRng::from_entropy())),
+        }
+    }
+
+    /// Echo `data` back on `stream`.
+    pub(crate) async fn send(
+        &mut self,
+        stream: &mut Stream,
+        mut data: &[u8],
+    ) -> std::io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        while !data.is_empty() {
+            let mut size = data.len();
+            if let Some((max, rng)) = &mut self.fragment {
+                size = size.min(rng.gen_range(1..=*max));
+            }
+            if let Some((rate, next)) = &mut self.throttle {
+                let step = (*rate as u128 * THROTTLE_STEP.as_millis() / 1000).max(1);
+                size = size.min(usize::try_from(step).unwrap_or(usize::MAX));
+
+                // Idle time is not saved up for a burst later, only the
+                // oversleeping of a step is made up for. Sleeps are rounded
+                // up to the millisecond, so shorter waits add up first
+                let now = Instant::now();
+                if *next + THROTTLE_STEP < now {
+                    *next = now;
+                }
+                if *next >= now + TIMER_RESOLUTION {
+                    tokio::time::sleep_until(*next).await;
+                }
+                *next += Duration::from_secs_f64(size as f64 / *rate as f64);
+            }
+
+            send_to_socket(stream, &data[..size]).await?;
+            data = &data[size..];
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+//! Echo through a pipe with `splice(2)`: the kernel moves the bytes from the
+//! socket to the pipe and back, without copying them to the server.
+
+use nix::fcntl::{fcntl, splice, FcntlArg, OFlag, SpliceFFlags};
+use nix::unistd::pipe2;
+use protocore::net::Context;
+use protocore::stream::Stream;
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+
+fn flags() -> SpliceFFlags {
+    SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_NONBLOCK
+}
+
+/// Echo `tcp`, the socket of `stream`, moving up to `chunk` bytes at once.
+pub(crate) async fn echo(
+    stream: &Stream,
+    tcp: &TcpStream,
+    context: &Context,
+    chunk: usize,
+) -> std::io::Result<()> {
+    let (pipe_out, pipe_in) = pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+
+    // The pipe is drained before reading again, so a chunk has to fit in it
+    // for a read never to block on the pipe rather than the socket
+    let size = i32::try_from(chunk).unwrap_or(i32::MAX);
+    let capacity = fcntl(pipe_in.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(size))
+        .or_else(|_| fcntl(pipe_in.as_raw_fd(), FcntlArg::F_GETPIPE_SZ))?;
+    let chunk = chunk.min(capacity as usize);
+
+    loop {
+        // Echo has no messages to speak of, only the idle timeout applies
+        let read = tcp.async_io(Interest::READABLE, || {
+            Ok(splice(tcp, None, &pipe_in, None, chunk, flags())?)
+        });
+        let n = context.timeouts().limit(None, read).await?;
+
+        if n == 0 {
+            return Ok(()); // EOF
+        }
+
+        let _request = context.start_request();
+        let mut left = n;
+        while left > 0 {
+            let written = tcp
+                .async_io(Interest::WRITABLE, || {
+                    Ok(splice(&pipe_out, None, tcp, None, left, flags())?)
+                })
+                .await?;
+            if written == 0 {
+                return Err(Error::new(ErrorKind::WriteZero, "the socket took nothing"));
+            }
+            left -= written;
+        }
+        stream.count_moved(n as u64, n as u64);
+    }
+}
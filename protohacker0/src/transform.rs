@@ -0,0 +1,142 @@
+//! Rewriting the echo, to test clients against a peer that does not send back
+//! exactly what it got.
+
+use clap::ValueEnum;
+use std::io::Write;
+
+/// What the echo sends back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Transform {
+    /// The bytes as they came
+    #[default]
+    None,
+    /// ASCII letters in upper case
+    Uppercase,
+    /// Each line reversed once it is whole, the newline staying last
+    ReverseLine,
+    /// A dump of the bytes, 16 per line, as `hexdump -C` prints them
+    HexDump,
+}
+
+/// Bytes of a hex dump line.
+const DUMP_WIDTH: usize = 16;
+
+impl Transform {
+    /// The reply to a datagram, transformed on its own.
+    pub fn apply(self, datagram: &[u8]) -> Vec<u8> {
+        let mut transformer = Transformer::new(self, datagram.len());
+        let mut out = Vec::new();
+        transformer.feed(datagram, &mut out);
+        transformer.finish(&mut out);
+        out
+    }
+}
+
+/// Transforms the bytes of a connection as they arrive, holding back the
+/// partial line or dump line until the rest of it comes in.
+pub(crate) struct Transformer {
+    transform: Transform,
+    /// Bytes held back, at most `max_pending`.
+    pending: Vec<u8>,
+    max_pending: usize,
+    /// Offset of the next dump line.
+    offset: u64,
+}
+
+impl Transformer {
+    /// A transformer holding back at most `max_pending` bytes: lines longer
+    /// than that are reversed in pieces.
+    pub(crate) fn new(transform: Transform, max_pending: usize) -> Transformer {
+        Transformer {
+            transform,
+            pending: Vec::new(),
+            max_pending: max_pending.max(1),
+            offset: 0,
+        }
+    }
+
+    /// Append what to echo for `input` to `out`, possibly nothing yet.
+    pub(crate) fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        match self.transform {
+            Transform::None => out.extend_from_slice(input),
+            Transform::Uppercase => out.extend(input.iter().map(u8::to_ascii_uppercase)),
+            Transform::ReverseLine => {
+                for &byte in input {
+                    if byte == b'\n' {
+                        reverse(&self.pending, out);
+                        out.push(b'\n');
+                        self.pending.clear();
+                    } else {
+                        self.pending.push(byte);
+                        if self.pending.len() == self.max_pending {
+                            reverse(&self.pending, out);
+                            self.pending.clear();
+                        }
+                    }
+                }
+            }
+            Transform::HexDump => {
+                for &byte in input {
+                    self.pending.push(byte);
+                    if self.pending.len() == DUMP_WIDTH {
+                        self.dump_line(out);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Append what was held back to `out`, once the client is done sending.
+    pub(crate) fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        match self.transform {
+            Transform::None | Transform::Uppercase => {}
+            Transform::ReverseLine => {
+                reverse(&self.pending, out);
+                self.pending.clear();
+            }
+            Transform::HexDump => self.dump_line(out),
+        }
+    }
+
+    /// Dump the pending bytes as a single line.
+    fn dump_line(&mut self, out: &mut Vec<u8>) {
+        let _ = write!(out, "{:08x}  ", self.offset);
+        for i in 0..DUMP_WIDTH {
+            match self.pending.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, "{byte:02x} ");
+                }
+                None => out.extend_from_slice(b"   "),
+            }
+            if i == DUMP_WIDTH / 2 - 1 {
+                out.push(b' ');
+            }
+        }
+
+        out.extend_from_slice(b" |");
+        out.extend(self.pending.iter().map(|&byte| match byte {
+            b' '..=b'~' => byte,
+            _ => b'.',
+        }));
+        out.extend_from_slice(b"|\n");
+
+        self.offset += self.pending.len() as u64;
+        self.pending.clear();
+    }
+}
+
+/// Append `line` reversed to `out`, by characters when it is UTF-8 so they
+/// stay readable, by bytes otherwise.
+fn reverse(line: &[u8], out: &mut Vec<u8>) {
+    match std::str::from_utf8(line) {
+        Ok(line) => {
+            let reversed: String = line.chars().rev().collect();
+            out.extend_from_slice(reversed.as_bytes());
+        }
+        Err(_) => out.extend(line.iter().rev()),
+    }
+}
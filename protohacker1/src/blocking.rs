@@ -0,0 +1,33 @@
+use crate::{check_request, generate_response, PrimeError, Settings};
+use protocore::blocking::io::{send_to_socket, LineReader};
+use protocore::io::ReadError;
+use std::net::TcpStream;
+
+fn take_requests(stream: TcpStream, settings: &Settings) -> Result<(), PrimeError> {
+    let mut reader = LineReader::new(stream, settings.max_request_size);
+
+    loop {
+        let request = match reader.read_line() {
+            Ok(request) => request,
+            Err(ReadError::Eof) => return Ok(()),
+            Err(e) => return Err(PrimeError::Read(e)),
+        };
+
+        let is_prime = check_request(&request);
+        let mut response = generate_response(&is_prime);
+        response.push(b'\n');
+        send_to_socket(reader.get_mut(), &response).map_err(PrimeError::Write)?;
+
+        // a malformed request closes the connection once answered
+        is_prime?;
+    }
+}
+
+pub fn handle_stream(stream: TcpStream) -> Result<(), PrimeError> {
+    take_requests(stream, &Settings::default())
+}
+
+/// Same as [`handle_stream`], with other settings than the defaults.
+pub fn handle_stream_with(stream: TcpStream, settings: &Settings) -> Result<(), PrimeError> {
+    take_requests(stream, settings)
+}
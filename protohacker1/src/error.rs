@@ -0,0 +1,35 @@
+use protocore::io::ReadError;
+use std::fmt;
+
+/// Why a prime time client was dropped.
+#[derive(Debug)]
+pub enum PrimeError {
+    Read(ReadError),
+    Write(std::io::Error),
+    /// The request is not a JSON object with the expected fields.
+    InvalidPayload(serde_json::Error),
+    /// The request asks for something other than `isPrime`.
+    InvalidMethod(String),
+}
+
+impl fmt::Display for PrimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrimeError::Read(e) => write!(f, "{e}"),
+            PrimeError::Write(e) => write!(f, "failed to send back to the client: {e}"),
+            PrimeError::InvalidPayload(e) => write!(f, "failed to parse the json payload: {e}"),
+            PrimeError::InvalidMethod(method) => write!(f, "invalid request method `{method}`"),
+        }
+    }
+}
+
+impl std::error::Error for PrimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PrimeError::Read(e) => Some(e),
+            PrimeError::Write(e) => Some(e),
+            PrimeError::InvalidPayload(e) => Some(e),
+            PrimeError::InvalidMethod(_) => None,
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use protocore::codec::LineCodec;
+use protocore::metrics::{self, Counter};
+use protocore::net::Server;
+use protocore::protocol::{Flow, Protocol, Replies};
+use protocore::reload::Reloadable;
+use serde::{Deserialize, Serialize};
+use std::net::TcpListener;
+use std::sync::{Arc, LazyLock};
+use tracing::{debug, trace};
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod error;
+mod primality;
+
+pub use error::PrimeError;
+pub use primality::is_prime;
+
+const VALID_METHOD: &str = "isPrime";
+const INVALID_METHOD: &str = "invalid";
+
+/// Longest request line we are willing to buffer, by default.
+pub const MAX_REQUEST_SIZE: usize = 1 << 16;
+
+/// Tunables of the prime time server.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Longest request line to buffer, newline included.
+    pub max_request_size: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_request_size: MAX_REQUEST_SIZE,
+        }
+    }
+}
+
+/// A request line, asking whether `number` is prime.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerRequest {
+    pub method: String,
+    pub number: f64,
+}
+
+impl ServerRequest {
+    pub fn is_prime(number: f64) -> ServerRequest {
+        ServerRequest {
+            method: VALID_METHOD.to_string(),
+            number,
+        }
+    }
+}
+
+/// The reply to a request line.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerReply {
+    pub method: String,
+    pub prime: bool,
+}
+
+impl ServerReply {
+    /// Whether this is the reply to a malformed request.
+    pub fn is_malformed(&self) -> bool {
+        self.method != VALID_METHOD
+    }
+}
+
+fn check_well_formated_request(request: &ServerRequest) -> Result<bool, PrimeError> {
+    if request.method != VALID_METHOD {
+        return Err(PrimeError::InvalidMethod(request.method.clone()));
+    }
+
+    let n = request.number;
+    let is_not_float = n.fract() == 0.0;
+
+    // Negative numbers saturate to 0, too large ones to u64::MAX, neither prime
+    let number = n as u64;
+    trace!(number, "checking primality");
+    Ok(is_not_float && is_prime(number))
+}
+
+/// Requests answered so far, by outcome: prime, composite or malformed.
+static CHECKS: LazyLock<[Arc<Counter>; 3]> = LazyLock::new(|| {
+    ["prime", "composite", "malformed"].map(|result| {
+        metrics::counter(
+            "protohacker_prime_checks_total",
+            "Prime checks answered, by result.",
+            &[("result", result)],
+        )
+    })
+});
+
+/// Parse a request line and tell whether the number it carries is prime.
+pub fn check_request(request: &[u8]) -> Result<bool, PrimeError> {
+    let is_prime = parse_and_check(request);
+
+    let outcome = match is_prime {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(_) => 2,
+    };
+    CHECKS[outcome].inc();
+
+    is_prime
+}
+
+fn parse_and_check(request: &[u8]) -> Result<bool, PrimeError> {
+    debug!(request = %String::from_utf8_lossy(request), "received request");
+
+    let request: ServerRequest =
+        serde_json::from_slice(request).map_err(PrimeError::InvalidPayload)?;
+
+    check_well_formated_request(&request)
+}
+
+/// Generate the response to a checked request, without its newline. A
+/// malformed request gets a malformed response.
+pub fn generate_response(is_prime: &Result<bool, PrimeError>) -> Vec<u8> {
+    let (method, prime) = match is_prime {
+        Ok(is_prime) => (VALID_METHOD, *is_prime),
+        Err(_) => (INVALID_METHOD, false),
+    };
+
+    let reply = ServerReply {
+        method: method.to_string(),
+        prime,
+    };
+
+    serde_json::to_vec(&reply).unwrap()
+}
+
+/// Prime time: newline delimited JSON requests, each answered in turn.
+#[derive(Default)]
+pub struct PrimeTime {
+    settings: Reloadable<Settings>,
+}
+
+impl PrimeTime {
+    /// Settings given as a [`Reloadable`] apply to the connections opened
+    /// after they change.
+    pub fn new(settings: impl Into<Reloadable<Settings>>) -> PrimeTime {
+        PrimeTime {
+            settings: settings.into(),
+        }
+    }
+}
+
+impl Protocol for PrimeTime {
+    type Codec = LineCodec;
+    type Session = ();
+    type Error = PrimeError;
+
+    fn codec(&self) -> LineCodec {
+        LineCodec::new(self.settings.get().max_request_size)
+    }
+
+    fn on_message(
+        &self,
+        _session: &mut (),
+        request: Vec<u8>,
+        replies: &mut Replies,
+    ) -> Result<Flow, PrimeError> {
+        let is_prime = check_request(&request);
+        replies.send(generate_response(&is_prime));
+
+        // a malformed request closes the connection once answered
+        is_prime?;
+        Ok(Flow::Continue)
+    }
+}
+
+/// Serve prime time on every listener until the server shuts down.
+pub async fn serve(server: &Server, listeners: Vec<TcpListener>) -> std::io::Result<()> {
+    serve_with(server, listeners, Settings::default()).await
+}
+
+/// Same as [`serve`], with other settings than the defaults.
+pub async fn serve_with(
+    server: &Server,
+    listeners: Vec<TcpListener>,
+    settings: impl Into<Reloadable<Settings>>,
+) -> std::io::Result<()> {
+    server
+        .serve_protocol(listeners, PrimeTime::new(settings))
+        .await
+}
@@ -0,0 +1,69 @@
+//! Primality of any `u64`, in a few dozen modular multiplications: trial
+//! division by the small primes, then a deterministic Miller-Rabin test.
+
+/// Primes trial division goes through. Any number left is coprime to them,
+/// so numbers below the square of the next prime, 53, are prime.
+const SMALL_PRIMES: [u64; 15] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47];
+
+/// Bases for which Miller-Rabin makes no mistake below 3.3e24, so for every
+/// `u64`.
+const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut result = 1;
+    base %= m;
+
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+
+    result
+}
+
+/// Whether `witness` proves the odd `n` composite, with `n - 1 = d * 2^s`
+/// and `d` odd.
+fn proves_composite(witness: u64, n: u64, d: u64, s: u32) -> bool {
+    let mut x = pow_mod(witness, d, n);
+    if x == 1 || x == n - 1 {
+        return false;
+    }
+
+    for _ in 1..s {
+        x = mul_mod(x, x, n);
+        if x == n - 1 {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Whether `number` is prime.
+pub fn is_prime(number: u64) -> bool {
+    if number < 2 {
+        return false;
+    }
+    for prime in SMALL_PRIMES {
+        if number.is_multiple_of(prime) {
+            return number == prime;
+        }
+    }
+    if number < 53 * 53 {
+        return true;
+    }
+
+    let s = (number - 1).trailing_zeros();
+    let d = (number - 1) >> s;
+
+    !WITNESSES
+        .iter()
+        .any(|&witness| proves_composite(witness, number, d, s))
+}
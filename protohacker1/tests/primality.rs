@@ -0,0 +1,85 @@
+//! The primality test, against a sieve and trial division.
+
+use proptest::prelude::*;
+use protohacker1::is_prime;
+use std::sync::LazyLock;
+
+/// Bound of the sieve the primality test is checked against.
+const SIEVE_LIMIT: usize = 1 << 20;
+
+/// Whether each number below `SIEVE_LIMIT` is prime, by the sieve of
+/// Eratosthenes.
+static SIEVE: LazyLock<Vec<bool>> = LazyLock::new(|| {
+    let mut sieve = vec![true; SIEVE_LIMIT];
+    sieve[0] = false;
+    sieve[1] = false;
+
+    let mut i = 2;
+    while i * i < SIEVE_LIMIT {
+        if sieve[i] {
+            for multiple in (i * i..SIEVE_LIMIT).step_by(i) {
+                sieve[multiple] = false;
+            }
+        }
+        i += 1;
+    }
+    sieve
+});
+
+/// The primes of the sieve.
+static PRIMES: LazyLock<Vec<u64>> = LazyLock::new(|| {
+    (0..SIEVE_LIMIT as u64)
+        .filter(|&n| SIEVE[n as usize])
+        .collect()
+});
+
+/// Whether `n`, below `SIEVE_LIMIT` squared, is prime, by trial division.
+fn divided_by_none(n: u64) -> bool {
+    n >= 2
+        && PRIMES
+            .iter()
+            .take_while(|&&p| p * p <= n)
+            .all(|&p| !n.is_multiple_of(p))
+}
+
+#[test]
+fn tells_hard_primes_apart() {
+    for prime in [
+        (1 << 61) - 1,
+        4_294_967_291,
+        18_446_744_073_709_551_557, // the largest u64 prime
+    ] {
+        assert!(is_prime(prime), "{prime} is prime");
+    }
+
+    // Carmichael numbers and strong pseudoprimes to the first bases
+    for composite in [
+        561,
+        3_215_031_751,
+        3_825_123_056_546_413_051,
+        u64::MAX,
+        4_294_967_291 * 2_147_483_647,
+    ] {
+        assert!(!is_prime(composite), "{composite} is composite");
+    }
+}
+
+proptest! {
+    #[test]
+    fn agrees_with_the_sieve(n in 0..SIEVE_LIMIT as u64) {
+        prop_assert_eq!(is_prime(n), SIEVE[n as usize]);
+    }
+
+    #[test]
+    fn agrees_with_trial_division(n in 0..(SIEVE_LIMIT as u64).pow(2)) {
+        prop_assert_eq!(is_prime(n), divided_by_none(n));
+    }
+
+    #[test]
+    fn finds_products_of_primes_composite(
+        p in prop::sample::select(PRIMES.clone()),
+        q in prop::sample::select(PRIMES.clone()),
+    ) {
+        prop_assert!(!is_prime(p * q));
+    }
+}
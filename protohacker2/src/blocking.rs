@@ -0,0 +1,24 @@
+use crate::{MeansError, Session, COMMAND_SIZE};
+use protocore::blocking::io::{read_frame, send_to_socket};
+use protocore::io::ReadError;
+use std::net::TcpStream;
+
+fn handle_client(stream: &mut TcpStream) -> Result<(), MeansError> {
+    let mut session = Session::new();
+
+    loop {
+        let buffer = match read_frame::<COMMAND_SIZE, _>(stream) {
+            Ok(buffer) => buffer,
+            Err(ReadError::Eof) => return Ok(()),
+            Err(e) => return Err(MeansError::Read(e)),
+        };
+
+        if let Some(response) = session.handle(&buffer)? {
+            send_to_socket(stream, &response.to_bytes()).map_err(MeansError::Write)?;
+        }
+    }
+}
+
+pub fn handle_stream(mut stream: TcpStream) -> Result<(), MeansError> {
+    handle_client(&mut stream)
+}
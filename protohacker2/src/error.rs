@@ -0,0 +1,31 @@
+use protocore::io::ReadError;
+use std::fmt;
+
+/// Why a means to an end client was dropped.
+#[derive(Debug)]
+pub enum MeansError {
+    Read(ReadError),
+    Write(std::io::Error),
+    /// The message type is neither `I` nor `Q`.
+    UnknownCommand(u8),
+}
+
+impl fmt::Display for MeansError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeansError::Read(e) => write!(f, "{e}"),
+            MeansError::Write(e) => write!(f, "failed to send back to the client: {e}"),
+            MeansError::UnknownCommand(c) => write!(f, "unknown command type {c:#04x}"),
+        }
+    }
+}
+
+impl std::error::Error for MeansError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeansError::Read(e) => Some(e),
+            MeansError::Write(e) => Some(e),
+            MeansError::UnknownCommand(_) => None,
+        }
+    }
+}
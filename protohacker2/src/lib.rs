@@ -0,0 +1,211 @@
+use protocore::codec::FixedFrameCodec;
+use protocore::metrics::{self, Counter};
+use protocore::net::Server;
+use protocore::protocol::{Flow, Protocol, Replies};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, LazyLock};
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod error;
+
+pub use error::MeansError;
+
+/// Size of a message sent by clients.
+pub const COMMAND_SIZE: usize = 9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandType {
+    Query,
+    Insert,
+}
+
+/// Commands received so far, by type.
+static COMMANDS: LazyLock<[Arc<Counter>; 2]> = LazyLock::new(|| {
+    ["query", "insert"].map(|kind| {
+        metrics::counter(
+            "protohacker_means_commands_total",
+            "Commands received, by type.",
+            &[("kind", kind)],
+        )
+    })
+});
+
+impl CommandType {
+    fn parse(data: u8) -> Result<CommandType, MeansError> {
+        match data {
+            b'I' => Ok(CommandType::Insert),
+            b'Q' => Ok(CommandType::Query),
+            _ => Err(MeansError::UnknownCommand(data)),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            CommandType::Insert => b'I',
+            CommandType::Query => b'Q',
+        }
+    }
+}
+
+/// A message sent by clients: an insert carries a timestamp and a price, a
+/// query the bounds of the period to average.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Command {
+    pub c_type: CommandType,
+    pub first_number: i32,
+    pub second_number: i32,
+}
+
+/// The reply to a query: the mean price over the period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub value: i32,
+}
+
+impl Response {
+    pub const SIZE: usize = 4;
+
+    pub fn parse(data: &[u8; Response::SIZE]) -> Response {
+        Response {
+            value: i32::from_be_bytes(*data),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Response::SIZE] {
+        self.value.to_be_bytes()
+    }
+}
+
+impl Command {
+    pub fn insert(timestamp: i32, price: i32) -> Command {
+        Command {
+            c_type: CommandType::Insert,
+            first_number: timestamp,
+            second_number: price,
+        }
+    }
+
+    pub fn query(min_time: i32, max_time: i32) -> Command {
+        Command {
+            c_type: CommandType::Query,
+            first_number: min_time,
+            second_number: max_time,
+        }
+    }
+
+    pub fn parse(data: &[u8; COMMAND_SIZE]) -> Result<Command, MeansError> {
+        let c_type = CommandType::parse(data[0])?;
+
+        let first_number = slice_to_i32_be(&data[1..5]);
+        let second_number = slice_to_i32_be(&data[5..9]);
+
+        Ok(Command {
+            c_type,
+            first_number,
+            second_number,
+        })
+    }
+
+    /// The message as sent on the wire.
+    pub fn to_bytes(&self) -> [u8; COMMAND_SIZE] {
+        let mut buff = [0; COMMAND_SIZE];
+        buff[0] = self.c_type.to_byte();
+        buff[1..5].copy_from_slice(&self.first_number.to_be_bytes());
+        buff[5..9].copy_from_slice(&self.second_number.to_be_bytes());
+        buff
+    }
+
+    fn generate_response(&self, datastore: &mut HashMap<i32, i32>) -> Option<Response> {
+        match self.c_type {
+            CommandType::Query => {
+                COMMANDS[0].inc();
+
+                let earliest = self.first_number;
+                let latest = self.second_number;
+
+                let v: Vec<i32> = datastore
+                    .iter()
+                    .filter(|(&k, _)| earliest <= k && k <= latest)
+                    .map(|(_, &v)| v)
+                    .collect();
+
+                let sz = v.len();
+
+                let avg = if sz == 0 {
+                    0
+                } else {
+                    let sum = v.iter().fold(0_i64, |acc, &element| acc + (element as i64));
+                    (sum / (sz as i64)) as i32
+                };
+
+                Some(Response { value: avg })
+            }
+            CommandType::Insert => {
+                COMMANDS[1].inc();
+                let timestamp = self.first_number;
+                let value = self.second_number;
+                datastore.entry(timestamp).or_insert(value);
+                None
+            }
+        }
+    }
+}
+
+fn slice_to_i32_be(data: &[u8]) -> i32 {
+    let mut buff: [u8; 4] = [0; 4];
+
+    buff[..4].copy_from_slice(data);
+
+    i32::from_be_bytes(buff)
+}
+
+/// The prices a client inserted; each connection has its own.
+#[derive(Default)]
+pub struct Session {
+    datastore: HashMap<i32, i32>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Apply a message received from the client, returning the reply to
+    /// queries.
+    pub fn handle(&mut self, frame: &[u8; COMMAND_SIZE]) -> Result<Option<Response>, MeansError> {
+        let cmd = Command::parse(frame)?;
+        Ok(cmd.generate_response(&mut self.datastore))
+    }
+}
+
+/// Means to an end: fixed-size commands, with a price history per connection.
+pub struct MeansToAnEnd;
+
+impl Protocol for MeansToAnEnd {
+    type Codec = FixedFrameCodec<COMMAND_SIZE>;
+    type Session = Session;
+    type Error = MeansError;
+
+    fn codec(&self) -> FixedFrameCodec<COMMAND_SIZE> {
+        FixedFrameCodec
+    }
+
+    fn on_message(
+        &self,
+        session: &mut Session,
+        frame: [u8; COMMAND_SIZE],
+        replies: &mut Replies,
+    ) -> Result<Flow, MeansError> {
+        if let Some(response) = session.handle(&frame)? {
+            replies.send(response.to_bytes());
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// Serve means to an end on every listener until the server shuts down.
+pub async fn serve(server: &Server, listeners: Vec<TcpListener>) -> std::io::Result<()> {
+    server.serve_protocol(listeners, MeansToAnEnd).await
+}
@@ -0,0 +1,147 @@
+use crate::{room_description, validate_username, ChatError, Event, Settings};
+use protocore::blocking::io::{send_to_socket, LineReader};
+use protocore::io::ReadError;
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Clients = Arc<Mutex<HashMap<String, TcpStream>>>;
+
+// Send the message to all clients except the one specified in the `but` field.
+fn send_to_all_but(message: &str, but: &str, clients: &mut HashMap<String, TcpStream>) {
+    for (username, stream) in clients.iter_mut() {
+        if username != but {
+            // A client we cannot write to is dropped by its own thread once
+            // its reads fail too.
+            let _ = send_to_socket(stream, message.as_bytes());
+        }
+    }
+}
+
+fn sender_thread(clients: Clients, rx: Receiver<Event>) {
+    loop {
+        let event = rx.recv().unwrap();
+
+        {
+            let mut clients_map = clients.lock().unwrap();
+            send_to_all_but(&event.to_line(), event.username(), &mut clients_map);
+        }
+    }
+}
+
+/// Perform the handshake with the new client: ask for the username and validate it.
+fn handshake(
+    reader: &mut LineReader<TcpStream>,
+    clients: &Clients,
+    settings: &Settings,
+) -> Result<String, ChatError> {
+    send_to_socket(reader.get_mut(), settings.welcome_line().as_bytes())
+        .map_err(ChatError::Write)?;
+
+    let uname = reader.read_line_utf8().map_err(ChatError::Read)?;
+
+    let clients_map = clients.lock().unwrap();
+    if !validate_username(&uname) {
+        Err(ChatError::InvalidUsername(uname))
+    } else if clients_map.contains_key(&uname) {
+        Err(ChatError::UsernameTaken(uname))
+    } else {
+        Ok(uname)
+    }
+}
+
+/// Receives messages from a client and distributes them upon reception
+fn receive_messages(
+    mut reader: LineReader<TcpStream>,
+    tx: Sender<Event>,
+    clients: Clients,
+    username: String,
+) -> Result<(), ChatError> {
+    let result = loop {
+        match reader.read_line_utf8() {
+            Ok(msg) => tx.send(Event::Sent(username.clone(), msg)).unwrap(),
+            Err(ReadError::Eof) => break Ok(()),
+            Err(e) => break Err(ChatError::Read(e)),
+        }
+    };
+
+    let mut clients_map = clients.lock().unwrap();
+    clients_map.remove_entry(&username).unwrap();
+    tx.send(Event::Left(username)).unwrap();
+
+    result
+}
+
+/// Send the list of members in the room to the stream
+fn send_room_description(stream: &mut TcpStream, clients: &Clients) -> std::io::Result<()> {
+    let clients_map = clients.lock().unwrap();
+
+    let in_room_message = room_description(clients_map.keys());
+
+    send_to_socket(stream, in_room_message.as_bytes())
+}
+
+/// Thread-per-connection version of [`crate::BudgetChat`].
+pub struct BudgetChat {
+    clients: Clients,
+    tx: Sender<Event>,
+    settings: Settings,
+}
+
+impl BudgetChat {
+    /// Create the room along with the thread fanning out its messages.
+    pub fn new() -> BudgetChat {
+        BudgetChat::with_settings(Settings::default())
+    }
+
+    pub fn with_settings(settings: Settings) -> BudgetChat {
+        let (tx, rx): (Sender<Event>, Receiver<Event>) = channel();
+        let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
+
+        // Create the sender thread
+        {
+            let clients = Arc::clone(&clients);
+            thread::spawn(move || sender_thread(clients, rx));
+        }
+
+        BudgetChat {
+            clients,
+            tx,
+            settings,
+        }
+    }
+
+    pub fn handle_stream(&self, stream: TcpStream) -> Result<(), ChatError> {
+        let clients = Arc::clone(&self.clients);
+        let tx = self.tx.clone();
+
+        let mut reader = LineReader::new(stream, self.settings.max_line_size);
+
+        let username = match handshake(&mut reader, &clients, &self.settings) {
+            Ok(username) => username,
+            Err(ChatError::Read(ReadError::Eof)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        send_room_description(reader.get_mut(), &clients).map_err(ChatError::Write)?;
+
+        {
+            // insert the client in the map and send the joined event
+            let writer = reader.get_mut().try_clone().map_err(ChatError::Write)?;
+            let mut clients_map = clients.lock().unwrap();
+            clients_map.insert(username.to_string(), writer);
+            tx.send(Event::Joined(username.to_string())).unwrap();
+        }
+
+        receive_messages(reader, tx, clients, username)
+    }
+}
+
+impl Default for BudgetChat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,34 @@
+use protocore::io::ReadError;
+use std::fmt;
+
+/// Why a budget chat client was dropped.
+#[derive(Debug)]
+pub enum ChatError {
+    Read(ReadError),
+    Write(std::io::Error),
+    /// The requested name is empty or not alphanumeric.
+    InvalidUsername(String),
+    /// Somebody in the room already goes by that name.
+    UsernameTaken(String),
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::Read(e) => write!(f, "{e}"),
+            ChatError::Write(e) => write!(f, "failed to send to the client: {e}"),
+            ChatError::InvalidUsername(name) => write!(f, "invalid username {name:?}"),
+            ChatError::UsernameTaken(name) => write!(f, "username {name:?} is already taken"),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChatError::Read(e) => Some(e),
+            ChatError::Write(e) => Some(e),
+            ChatError::InvalidUsername(_) | ChatError::UsernameTaken(_) => None,
+        }
+    }
+}
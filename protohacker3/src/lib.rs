@@ -0,0 +1,380 @@
+use protocore::io::{send_to_socket, LineReader, ReadError};
+use protocore::metrics::{self, Counter, Gauge};
+use protocore::net::{Context, Server};
+use protocore::reload::Reloadable;
+use protocore::stream::Stream;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::io::AsyncWrite;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod error;
+
+pub use error::ChatError;
+
+pub const WELCOME_MESSAGE: &str = "Welcome to budgetchat! What shall I call you?\n";
+pub const SHUTDOWN_MESSAGE: &str = "* server shutting down\n";
+
+/// Maximum length of a chat line, newline included.
+pub const MAX_LINE_SIZE: usize = 1024;
+
+/// Tunables of the chat server.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Line sent to new clients to ask for their name, without its newline.
+    pub welcome: String,
+    /// Longest line a client may send, newline included.
+    pub max_line_size: usize,
+}
+
+impl Settings {
+    /// The welcome line, as sent to the socket.
+    fn welcome_line(&self) -> String {
+        format!("{}\n", self.welcome)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            welcome: WELCOME_MESSAGE.trim_end().to_string(),
+            max_line_size: MAX_LINE_SIZE,
+        }
+    }
+}
+
+/// Something happening in the room, sent to every user but the one it is about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Joined(String),
+    Left(String),
+    Sent(String, String),
+}
+
+impl Event {
+    /// The user this event is about; they are not sent the event.
+    pub fn username(&self) -> &str {
+        match self {
+            Event::Joined(username) | Event::Left(username) | Event::Sent(username, _) => username,
+        }
+    }
+
+    /// Parse a line sent by the server, without its newline. Lines that are
+    /// not events, e.g. the welcome message, give `None`.
+    pub fn from_line(line: &str) -> Option<Event> {
+        if let Some(rest) = line.strip_prefix('[') {
+            let (username, message) = rest.split_once("] ")?;
+            return Some(Event::Sent(username.to_string(), message.to_string()));
+        }
+
+        let rest = line.strip_prefix("* ")?;
+        if let Some(username) = rest.strip_suffix(" has joined the room") {
+            Some(Event::Joined(username.to_string()))
+        } else {
+            rest.strip_suffix(" has left the room")
+                .map(|username| Event::Left(username.to_string()))
+        }
+    }
+
+    /// The line sent to the other users of the room.
+    pub fn to_line(&self) -> String {
+        match self {
+            Event::Joined(username) => format!("* {username} has joined the room\n"),
+            Event::Left(username) => format!("* {username} has left the room\n"),
+            Event::Sent(username, message) => format!("[{username}] {message}\n"),
+        }
+    }
+}
+
+/// Usernames are non-empty and only made of ASCII letters and digits.
+pub fn validate_username(s: &str) -> bool {
+    if s.is_empty() {
+        return false;
+    }
+
+    if !s.is_ascii() {
+        return false;
+    }
+
+    s.chars().all(|x| x.is_alphanumeric())
+}
+
+/// The members listed in a room description, as sent to a user joining it.
+pub fn parse_room_description(line: &str) -> Option<Vec<String>> {
+    let in_room = line.strip_prefix("*Welcome. Users in room: ")?;
+
+    Some(
+        in_room
+            .split(", ")
+            .filter(|username| !username.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// The list of members in the room, as sent to a user joining it.
+fn room_description<'a>(usernames: impl Iterator<Item = &'a String>) -> String {
+    let in_room = usernames.fold(String::new(), |acc, ele| acc + ", " + ele);
+
+    format!("*Welcome. Users in room: {in_room}\n")
+}
+
+static MESSAGES: LazyLock<Arc<Counter>> = LazyLock::new(|| {
+    metrics::counter(
+        "protohacker_chat_messages_total",
+        "Chat messages sent by users.",
+        &[],
+    )
+});
+
+static DELIVERIES: LazyLock<Arc<Counter>> = LazyLock::new(|| {
+    metrics::counter(
+        "protohacker_chat_deliveries_total",
+        "Lines queued for users, as messages and presence events fan out.",
+        &[],
+    )
+});
+
+static USERS: LazyLock<Arc<Gauge>> = LazyLock::new(|| {
+    metrics::gauge(
+        "protohacker_chat_users",
+        "Users currently in the room.",
+        &[],
+    )
+});
+
+/// Outgoing lines of each user in the room.
+type Clients = HashMap<String, UnboundedSender<Arc<str>>>;
+
+// Send the message to all clients except the one specified in the `but` field.
+fn send_to_all_but(message: &str, but: &str, clients: &Clients) {
+    let message: Arc<str> = Arc::from(message);
+
+    for (username, outbox) in clients.iter() {
+        if username != but {
+            // A closed outbox belongs to a client on its way out, who will be
+            // removed from the room shortly.
+            if outbox.send(Arc::clone(&message)).is_ok() {
+                DELIVERIES.inc();
+            }
+        }
+    }
+}
+
+/// Forward the lines queued for a client to its socket, until the client
+/// leaves the room or the socket fails.
+async fn send_messages<W: AsyncWrite + Unpin>(
+    mut stream: W,
+    mut outbox: UnboundedReceiver<Arc<str>>,
+) {
+    while let Some(message) = outbox.recv().await {
+        if send_to_socket(&mut stream, message.as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Keeps a user in the room until dropped, so that a handler dropped midway,
+/// e.g. when the client is kicked, still leaves it.
+struct Member<'a> {
+    chat: &'a BudgetChat,
+    username: &'a str,
+    context: &'a Context,
+}
+
+impl Drop for Member<'_> {
+    fn drop(&mut self) {
+        self.chat.leave(self.username, !self.context.is_closing());
+    }
+}
+
+/// A single chat room. Every connection handled by the same `BudgetChat` sees
+/// the messages of the others.
+///
+/// Each user gets a queue of outgoing lines drained by its own task, so a slow
+/// reader never holds up the rest of the room.
+pub struct BudgetChat {
+    clients: Mutex<Clients>,
+    settings: Reloadable<Settings>,
+}
+
+impl BudgetChat {
+    pub fn new() -> BudgetChat {
+        BudgetChat::with_settings(Settings::default())
+    }
+
+    /// Settings given as a [`Reloadable`] apply to the users joining after
+    /// they change; those in the room keep theirs.
+    pub fn with_settings(settings: impl Into<Reloadable<Settings>>) -> BudgetChat {
+        BudgetChat {
+            clients: Mutex::new(HashMap::new()),
+            settings: settings.into(),
+        }
+    }
+
+    /// Add the user to the room if the name is free, queueing the room
+    /// description for them and announcing them to the others.
+    pub fn join(
+        &self,
+        username: &str,
+        outbox: &UnboundedSender<Arc<str>>,
+    ) -> Result<(), ChatError> {
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.contains_key(username) {
+            return Err(ChatError::UsernameTaken(username.to_string()));
+        }
+
+        let _ = outbox.send(Arc::from(room_description(clients.keys())));
+        clients.insert(username.to_string(), outbox.clone());
+        USERS.set(clients.len() as i64);
+
+        send_to_all_but(
+            &Event::Joined(username.to_string()).to_line(),
+            username,
+            &clients,
+        );
+
+        Ok(())
+    }
+
+    /// Send the event to every user but the one it is about.
+    pub fn broadcast(&self, event: Event) {
+        let clients = self.clients.lock().unwrap();
+        send_to_all_but(&event.to_line(), event.username(), &clients);
+    }
+
+    /// Remove the user from the room. The others are told unless the whole
+    /// room is going away.
+    pub fn leave(&self, username: &str, announce: bool) {
+        {
+            let mut clients = self.clients.lock().unwrap();
+            clients.remove(username);
+            USERS.set(clients.len() as i64);
+        }
+
+        if announce {
+            self.broadcast(Event::Left(username.to_string()));
+        }
+    }
+
+    /// Names of the users in the room, one per line, sorted.
+    pub fn dump(&self) -> String {
+        let clients = self.clients.lock().unwrap();
+        let mut usernames: Vec<_> = clients.keys().collect();
+        usernames.sort();
+        usernames
+            .iter()
+            .map(|username| format!("{username}\n"))
+            .collect()
+    }
+
+    /// Serve a client until they leave or the server shuts down, in which case
+    /// they are told so before being disconnected.
+    pub async fn handle_stream(&self, stream: Stream, context: Context) -> Result<(), ChatError> {
+        let settings = self.settings.get();
+        let (read, mut write) = stream.into_split();
+        let mut reader =
+            LineReader::new(read, settings.max_line_size).with_timeouts(context.timeouts().clone());
+
+        send_to_socket(&mut write, settings.welcome_line().as_bytes())
+            .await
+            .map_err(ChatError::Write)?;
+
+        // Perform the handshake with the new client: ask for the username and validate it.
+        let username = tokio::select! {
+            _ = context.closing() => {
+                return send_to_socket(&mut write, SHUTDOWN_MESSAGE.as_bytes())
+                    .await
+                    .map_err(ChatError::Write);
+            }
+            username = reader.read_line_utf8() => username,
+        };
+
+        let username = match username {
+            Ok(username) => username,
+            Err(ReadError::Eof) => return Ok(()),
+            Err(e) => return Err(ChatError::Read(e)),
+        };
+
+        if !validate_username(&username) {
+            return Err(ChatError::InvalidUsername(username));
+        }
+
+        let (outbox, rx) = unbounded_channel();
+        self.join(&username, &outbox)?;
+        let member = Member {
+            chat: self,
+            username: &username,
+            context: &context,
+        };
+
+        let sender = tokio::spawn(send_messages(write, rx));
+
+        // Receives messages from a client and distributes them upon reception
+        let result = loop {
+            let line = tokio::select! {
+                _ = context.closing() => {
+                    let _ = outbox.send(Arc::from(SHUTDOWN_MESSAGE));
+                    break Ok(());
+                }
+                line = reader.read_line_utf8() => line,
+            };
+
+            match line {
+                Ok(message) => {
+                    let _request = context.start_request();
+                    MESSAGES.inc();
+                    self.broadcast(Event::Sent(username.clone(), message));
+                }
+                Err(ReadError::Eof) => break Ok(()),
+                Err(e) => break Err(ChatError::Read(e)),
+            }
+        };
+
+        drop(member);
+
+        // Let the lines already queued for the client go out before closing
+        drop(outbox);
+        let _ = sender.await;
+
+        result
+    }
+}
+
+impl Default for BudgetChat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serve a single chat room on every listener until the server shuts down.
+pub async fn serve(server: &Server, listeners: Vec<TcpListener>) -> std::io::Result<()> {
+    serve_with(server, listeners, Settings::default()).await
+}
+
+/// Same as [`serve`], with other settings than the defaults.
+pub async fn serve_with(
+    server: &Server,
+    listeners: Vec<TcpListener>,
+    settings: impl Into<Reloadable<Settings>>,
+) -> std::io::Result<()> {
+    let chat = Arc::new(BudgetChat::with_settings(settings));
+
+    let room = Arc::downgrade(&chat);
+    server.set_dump(move || room.upgrade().map(|chat| chat.dump()).unwrap_or_default());
+
+    // Create a client task for each connection
+    server
+        .serve_tcp(listeners, move |stream, context| {
+            let chat = Arc::clone(&chat);
+            async move { chat.handle_stream(stream, context).await }
+        })
+        .await
+}
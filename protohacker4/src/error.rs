@@ -0,0 +1,31 @@
+use std::fmt;
+use std::string::FromUtf8Error;
+
+/// Why a request datagram was ignored.
+#[derive(Debug)]
+pub enum KvError {
+    /// Keys and values are text; the datagram is not.
+    NotUtf8(FromUtf8Error),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::NotUtf8(e) => write!(f, "request is not valid UTF-8: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for KvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KvError::NotUtf8(e) => Some(e),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for KvError {
+    fn from(e: FromUtf8Error) -> Self {
+        KvError::NotUtf8(e)
+    }
+}
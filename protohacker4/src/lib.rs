@@ -0,0 +1,208 @@
+use protocore::codec::DatagramCodec;
+use protocore::metrics::{self, Counter};
+use protocore::net::Server;
+use protocore::protocol::{Flow, Protocol, Replies};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::ops::Deref;
+use std::sync::{Arc, LazyLock, Mutex};
+use tracing::debug;
+
+mod error;
+
+pub use error::KvError;
+
+/// Largest datagram handled or sent back, by default. Longer requests are
+/// truncated.
+pub const BUFF_SIZE: usize = 1000;
+const NO_VAL_KEY: &str = "";
+pub const VERSION_KEY: &str = "version";
+
+fn index_of_equal(buff: &[u8], sz: usize) -> Option<usize> {
+    let mut i = 0;
+
+    while i < sz {
+        if buff[i] == b'=' {
+            break;
+        }
+        i += 1;
+    }
+
+    if i == sz {
+        None
+    } else {
+        Some(i)
+    }
+}
+
+fn query(key: String, store: &HashMap<String, String>, buff: &mut [u8]) -> usize {
+    let dflt = String::from(NO_VAL_KEY);
+    let value = store.get(key.deref()).unwrap_or(&dflt);
+    debug!(key, value, "query");
+
+    let result = format!("{key}={value}");
+    let bts = result.as_bytes();
+
+    let total = min(buff.len(), bts.len());
+    buff[..total].copy_from_slice(&bts[..total]);
+
+    total
+}
+
+fn insert(buff: &[u8], eq_pos: usize, store: &mut HashMap<String, String>) -> Result<(), KvError> {
+    let key = String::from_utf8(buff[..eq_pos].to_vec())?;
+    let value = String::from_utf8(buff[eq_pos + 1..].to_vec())?;
+
+    if key == VERSION_KEY {
+        return Ok(());
+    };
+
+    store.insert(key, value);
+    Ok(())
+}
+
+/// Requests received so far, by type.
+static REQUESTS: LazyLock<[Arc<Counter>; 2]> = LazyLock::new(|| {
+    ["query", "insert"].map(|kind| {
+        metrics::counter(
+            "protohacker_kv_requests_total",
+            "Requests received, by type.",
+            &[("kind", kind)],
+        )
+    })
+});
+
+/// Tunables of the unusual database program.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Largest datagram handled or sent back.
+    pub max_datagram_size: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_datagram_size: BUFF_SIZE,
+        }
+    }
+}
+
+/// The key-value store behind the unusual database program.
+pub struct Database {
+    store: HashMap<String, String>,
+    send_buff: Vec<u8>,
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database::with_settings(&Settings::default())
+    }
+
+    /// A database whose replies are cut to `settings.max_datagram_size`.
+    pub fn with_settings(settings: &Settings) -> Database {
+        let mut store: HashMap<String, String> = HashMap::new();
+        store.insert(VERSION_KEY.to_string(), "1.0".to_string());
+
+        Database {
+            store,
+            send_buff: vec![0; settings.max_datagram_size],
+        }
+    }
+
+    /// Apply a single request datagram, returning the reply for queries.
+    pub fn handle_datagram(&mut self, request: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        let eq_pos = index_of_equal(request, request.len());
+
+        if let Some(eq_idx) = eq_pos {
+            REQUESTS[1].inc();
+            insert(request, eq_idx, &mut self.store)?;
+            Ok(None)
+        } else {
+            REQUESTS[0].inc();
+            let key = String::from_utf8(request.to_vec())?;
+            let sz = query(key, &self.store, &mut self.send_buff);
+            Ok(Some(self.send_buff[..sz].to_vec()))
+        }
+    }
+
+    /// Every key and its value, one `key=value` per line sorted by key, with
+    /// newlines and other control characters escaped.
+    pub fn dump(&self) -> String {
+        let mut entries: Vec<_> = self.store.iter().collect();
+        entries.sort();
+        entries
+            .into_iter()
+            .map(|(key, value)| format!("{}={}\n", key.escape_debug(), value.escape_debug()))
+            .collect()
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Unusual database program: a datagram is a request, answered by a datagram
+/// for queries. The database is shared by every client.
+#[derive(Default)]
+pub struct UnusualDatabase {
+    database: Arc<Mutex<Database>>,
+    settings: Settings,
+}
+
+impl UnusualDatabase {
+    pub fn new(settings: Settings) -> UnusualDatabase {
+        UnusualDatabase {
+            database: Arc::new(Mutex::new(Database::with_settings(&settings))),
+            settings,
+        }
+    }
+}
+
+impl Protocol for UnusualDatabase {
+    type Codec = DatagramCodec;
+    type Session = ();
+    type Error = KvError;
+
+    fn codec(&self) -> DatagramCodec {
+        DatagramCodec::new(self.settings.max_datagram_size)
+    }
+
+    fn on_message(
+        &self,
+        _session: &mut (),
+        request: Vec<u8>,
+        replies: &mut Replies,
+    ) -> Result<Flow, KvError> {
+        if let Some(reply) = self.database.lock().unwrap().handle_datagram(&request)? {
+            replies.send(reply);
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// Serve a fresh database on every socket until the server shuts down.
+pub async fn serve(server: &Server, sockets: Vec<UdpSocket>) -> std::io::Result<()> {
+    serve_with(server, sockets, Settings::default()).await
+}
+
+/// Same as [`serve`], with other settings than the defaults.
+pub async fn serve_with(
+    server: &Server,
+    sockets: Vec<UdpSocket>,
+    settings: Settings,
+) -> std::io::Result<()> {
+    let database = UnusualDatabase::new(settings);
+
+    let store = Arc::downgrade(&database.database);
+    server.set_dump(move || {
+        store
+            .upgrade()
+            .map(|database| database.lock().unwrap().dump())
+            .unwrap_or_default()
+    });
+
+    server.serve_datagram_protocol(sockets, database).await
+}
@@ -0,0 +1,106 @@
+use crate::{ProxyError, Settings, Upstream, CLIENT_TO_TONY, TONY_TO_CLIENT};
+use protocore::blocking::io::{send_to_socket, LineReader};
+use protocore::io::ReadError;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{debug, info, warn};
+
+/// Get a connection towards the upstream server
+fn tcp_to_tony(upstream: &Upstream) -> std::io::Result<TcpStream> {
+    TcpStream::connect((upstream.host.as_str(), upstream.port))
+}
+
+/// Forward a single line from `source` to `target`, rewriting boguscoin addresses.
+fn forward_line(
+    name: &str,
+    settings: &Settings,
+    source: &mut LineReader<TcpStream>,
+    target: &mut TcpStream,
+) -> Result<(), ProxyError> {
+    let message = source.read_line_utf8().map_err(ProxyError::Read)?;
+
+    // replace with tony's and add the lost newline back
+    let new_message = settings.rewrite(&message);
+
+    if new_message.trim_end_matches('\n') != message {
+        info!(
+            direction = name,
+            original = message,
+            rewritten = new_message,
+            "rewrote a boguscoin address"
+        );
+    } else {
+        debug!(direction = name, message, "forwarding");
+    }
+
+    send_to_socket(target, new_message.as_bytes()).map_err(ProxyError::Write)
+}
+
+fn proxy_and_rewrite(
+    name: &str,
+    source: TcpStream,
+    mut target: TcpStream,
+    alive: Arc<Mutex<bool>>,
+    settings: Arc<Settings>,
+) {
+    let mut source = LineReader::new(source, settings.max_line_size);
+    loop {
+        // check if either connection has dropped
+        {
+            let is_alive = alive.lock().unwrap();
+            if !*is_alive {
+                debug!(direction = name, "the other direction is closed");
+                break;
+            }
+        }
+
+        if let Err(e) = forward_line(name, &settings, &mut source, &mut target) {
+            if !matches!(e, ProxyError::Read(ReadError::Eof)) {
+                warn!(direction = name, error = %e, "proxy failed");
+            }
+
+            debug!(direction = name, "closing the session");
+            let mut is_alive = alive.lock().unwrap();
+            *is_alive = false;
+
+            // this is a bit dirty bc we are closing the write end
+            if target.shutdown(std::net::Shutdown::Both).is_err() {
+                debug!(direction = name, "the other side is already closed");
+            };
+
+            break;
+        }
+    }
+}
+
+pub fn establish_proxy(
+    client_stream: TcpStream,
+    settings: &Arc<Settings>,
+) -> Result<(), ProxyError> {
+    let alive = Arc::new(Mutex::new(true));
+
+    // > For each client that connects to your proxy server, you'll make a corresponding outward connection to the upstream server
+    let tony_stream = tcp_to_tony(&settings.upstream).map_err(ProxyError::Upstream)?;
+
+    let tony_read = tony_stream.try_clone().map_err(ProxyError::Upstream)?;
+    let client_read = client_stream.try_clone().map_err(ProxyError::Write)?;
+
+    {
+        let alive = Arc::clone(&alive);
+        let settings = Arc::clone(settings);
+        thread::spawn(move || {
+            proxy_and_rewrite(CLIENT_TO_TONY, client_read, tony_stream, alive, settings)
+        });
+    }
+
+    {
+        let alive = Arc::clone(&alive);
+        let settings = Arc::clone(settings);
+        thread::spawn(move || {
+            proxy_and_rewrite(TONY_TO_CLIENT, tony_read, client_stream, alive, settings)
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use protocore::io::ReadError;
+use std::fmt;
+
+/// Why a proxy session was torn down.
+#[derive(Debug)]
+pub enum ProxyError {
+    /// Could not reach the upstream chat server.
+    Upstream(std::io::Error),
+    Read(ReadError),
+    Write(std::io::Error),
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::Upstream(e) => write!(f, "failed to connect to upstream: {e}"),
+            ProxyError::Read(e) => write!(f, "{e}"),
+            ProxyError::Write(e) => write!(f, "failed to forward a message: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProxyError::Upstream(e) => Some(e),
+            ProxyError::Read(e) => Some(e),
+            ProxyError::Write(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use protocore::io::{send_to_socket, LineReader, ReadError};
+use protocore::limits::Timeouts;
+use protocore::metrics::{self, Counter};
+use protocore::net::{Context, Server};
+use protocore::reload::Reloadable;
+use protocore::stream::Stream;
+use protocore::tls::{self, TlsConnector};
+use regex::Regex;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, info};
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod error;
+
+pub use error::ProxyError;
+
+const TONY_BOGUS: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
+const TONY_SERVER_URL: &str = "chat.protohackers.com";
+const CLIENT_TO_TONY: &str = "[client=>tony]";
+const TONY_TO_CLIENT: &str = "[tony=>client]";
+/// A boguscoin address: a 7 followed by 25 to 34 ASCII letters or digits.
+pub const BOGUS_REGEX: &str = r"7[[:alnum:]]{25,34}";
+const TONY_SERVER_PORT: u16 = 16963;
+
+/// Maximum length of a chat line, newline included.
+pub const MAX_LINE_SIZE: usize = 1024;
+
+/// How long connecting to the upstream, TLS handshake included, may take.
+pub const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Scheme of the upstreams spoken to over TLS, e.g. `tls://chat.example:16963`.
+const TLS_SCHEME: &str = "tls://";
+
+/// The chat server the proxy sends its clients to. Defaults to Tony's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    pub host: String,
+    pub port: u16,
+    /// Connect over TLS, written `tls://HOST:PORT`.
+    pub tls: bool,
+    /// Certificates to trust for TLS instead of the web authorities, e.g.
+    /// for a self-signed upstream.
+    pub ca: Option<PathBuf>,
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Upstream {
+            host: TONY_SERVER_URL.to_string(),
+            port: TONY_SERVER_PORT,
+            tls: false,
+            ca: None,
+        }
+    }
+}
+
+impl Upstream {
+    /// The connector to reach the upstream with, if it speaks TLS.
+    pub fn connector(&self) -> std::io::Result<Option<TlsConnector>> {
+        self.tls
+            .then(|| tls::connector(self.ca.as_deref()))
+            .transpose()
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tls {
+            write!(f, "{TLS_SCHEME}")?;
+        }
+
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tls, s) = match s.strip_prefix(TLS_SCHEME) {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected HOST:PORT, got `{s}`"))?;
+
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        let port = port
+            .parse()
+            .map_err(|e| format!("invalid port `{port}`: {e}"))?;
+
+        Ok(Upstream {
+            host: host.to_string(),
+            port,
+            tls,
+            ca: None,
+        })
+    }
+}
+
+/// Tunables of the proxy.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub upstream: Upstream,
+    /// Boguscoin address substituted for the ones sent by clients.
+    pub address: String,
+    /// What a boguscoin address looks like.
+    pub pattern: Regex,
+    /// Longest line forwarded either way, newline included.
+    pub max_line_size: usize,
+    /// How long connecting to the upstream, TLS handshake included, may take
+    /// before the client is dropped.
+    pub upstream_timeout: Duration,
+}
+
+impl Settings {
+    /// Settings proxying to `upstream`, defaults otherwise.
+    pub fn with_upstream(upstream: Upstream) -> Settings {
+        Settings {
+            upstream,
+            ..Settings::default()
+        }
+    }
+
+    /// Replace the boguscoin addresses of a line, given without its newline,
+    /// with [`Settings::address`]. The newline is added back.
+    pub fn rewrite(&self, message: &str) -> String {
+        rewrite(&self.pattern, &self.address, message)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            upstream: Upstream::default(),
+            address: TONY_BOGUS.to_string(),
+            pattern: Regex::new(BOGUS_REGEX).unwrap(),
+            max_line_size: MAX_LINE_SIZE,
+            upstream_timeout: UPSTREAM_TIMEOUT,
+        }
+    }
+}
+
+static REWRITES: LazyLock<Arc<Counter>> = LazyLock::new(|| {
+    metrics::counter(
+        "protohacker_mitm_rewrites_total",
+        "Boguscoin addresses replaced with Tony's.",
+        &[],
+    )
+});
+
+/// Replace the boguscoin addresses of a line, given without its newline, with
+/// Tony's. `regex` is [`BOGUS_REGEX`]. The newline is added back.
+pub fn rewrite_message(regex: &Regex, message: &str) -> String {
+    rewrite(regex, TONY_BOGUS, message)
+}
+
+fn rewrite(regex: &Regex, address: &str, message: &str) -> String {
+    let mut result = String::with_capacity(message.len() + 1);
+    let mut copied = 0;
+    let matches = regex.find_iter(message);
+
+    let msg_len = message.len();
+    let msg_bts = message.as_bytes();
+
+    for re_match in matches {
+        let s = re_match.start();
+        let e = re_match.end();
+
+        // not a match if not at the start and not preceeded by a space
+        if 0 < s && msg_bts[s - 1] != b' ' {
+            continue;
+        }
+
+        // not a match if not at the end and not followed by a space
+        if e < msg_len && msg_bts[e] != b' ' {
+            continue;
+        }
+
+        // a match :) only this occurrence is replaced, the same text may
+        // appear elsewhere as part of a longer word
+        REWRITES.inc();
+        result.push_str(&message[copied..s]);
+        result.push_str(address);
+        copied = e;
+    }
+
+    result.push_str(&message[copied..]);
+    result.push('\n');
+
+    result
+}
+
+/// Forward lines from `source` to `target`, rewriting boguscoin addresses, until
+/// either side fails or closes.
+pub async fn proxy_and_rewrite<R, W>(
+    name: &str,
+    source: R,
+    mut target: W,
+    settings: &Settings,
+    timeouts: Timeouts,
+) -> Result<(), ProxyError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut source = LineReader::new(source, settings.max_line_size).with_timeouts(timeouts);
+    let lines = metrics::counter(
+        "protohacker_mitm_lines_total",
+        "Lines forwarded, by direction.",
+        &[("direction", name.trim_matches(['[', ']']))],
+    );
+
+    loop {
+        let message = match source.read_line_utf8().await {
+            Ok(message) => message,
+            Err(ReadError::Eof) => return Ok(()),
+            Err(e) => return Err(ProxyError::Read(e)),
+        };
+
+        // replace with tony's and add the lost newline back
+        let new_message = settings.rewrite(&message);
+        lines.inc();
+
+        if new_message.trim_end_matches('\n') != message {
+            info!(
+                direction = name,
+                original = message,
+                rewritten = new_message,
+                "rewrote a boguscoin address"
+            );
+        } else {
+            debug!(direction = name, message, "forwarding");
+        }
+
+        send_to_socket(&mut target, new_message.as_bytes())
+            .await
+            .map_err(ProxyError::Write)?;
+    }
+}
+
+/// Proxy a client to the upstream chat server, over TLS when given a
+/// connector. Sessions are not interrupted by a shutdown; they get the grace
+/// period to finish.
+pub async fn establish_proxy(
+    client_stream: Stream,
+    context: Context,
+    settings: &Settings,
+    tls: Option<&TlsConnector>,
+) -> Result<(), ProxyError> {
+    let upstream = &settings.upstream;
+    let deadline = Instant::now() + settings.upstream_timeout;
+
+    // > For each client that connects to your proxy server, you'll make a corresponding outward connection to the upstream server
+    let tony_stream = until(
+        deadline,
+        TcpStream::connect((upstream.host.as_str(), upstream.port)),
+    )
+    .await?;
+
+    match tls {
+        None => proxy(client_stream, tony_stream, &context, settings).await,
+        Some(connector) => {
+            let name = tls::server_name(&upstream.host).map_err(ProxyError::Upstream)?;
+            let tony_stream = until(deadline, connector.connect(name, tony_stream)).await?;
+            proxy(client_stream, tony_stream, &context, settings).await
+        }
+    }
+}
+
+/// Run a step of connecting to the upstream, giving up at `deadline`.
+async fn until<T>(
+    deadline: Instant,
+    step: impl Future<Output = io::Result<T>>,
+) -> Result<T, ProxyError> {
+    let result = timeout_at(deadline, step).await.unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the upstream did not answer in time",
+        ))
+    });
+    result.map_err(ProxyError::Upstream)
+}
+
+/// Forward both ways between a client and its upstream connection.
+async fn proxy<S>(
+    client_stream: Stream,
+    tony_stream: S,
+    context: &Context,
+    settings: &Settings,
+) -> Result<(), ProxyError>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (client_read, client_write) = client_stream.into_split();
+    let (tony_read, tony_write) = tokio::io::split(tony_stream);
+
+    // Only the client is held to the deadlines; upstream is trusted.
+    let client_timeouts = context.timeouts().clone();
+
+    // Once either direction is done, dropping both halves of both streams
+    // closes the whole session.
+    tokio::select! {
+        result = proxy_and_rewrite(CLIENT_TO_TONY, client_read, tony_write, settings, client_timeouts) => result,
+        result = proxy_and_rewrite(TONY_TO_CLIENT, tony_read, client_write, settings, Timeouts::default()) => result,
+    }
+}
+
+/// Proxy the clients of every listener to `upstream` until the server shuts
+/// down.
+pub async fn serve(
+    server: &Server,
+    listeners: Vec<TcpListener>,
+    upstream: Upstream,
+) -> std::io::Result<()> {
+    serve_with(server, listeners, Settings::with_upstream(upstream)).await
+}
+
+/// Same as [`serve`], with other settings than the defaults.
+///
+/// Settings given as a [`Reloadable`] apply to the clients connecting after
+/// they change. Whether the upstream speaks TLS, and the certificates it is
+/// checked against, are set once and for all by the first settings.
+pub async fn serve_with(
+    server: &Server,
+    listeners: Vec<TcpListener>,
+    settings: impl Into<Reloadable<Settings>>,
+) -> std::io::Result<()> {
+    let settings = settings.into();
+    let tls = settings.get().upstream.connector()?;
+
+    // Create a client task for each connection
+    server
+        .serve_tcp(listeners, move |stream, context| {
+            let settings = settings.get();
+            let tls = tls.clone();
+            async move { establish_proxy(stream, context, &settings, tls.as_ref()).await }
+        })
+        .await
+}
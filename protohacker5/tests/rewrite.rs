@@ -0,0 +1,54 @@
+//! Rewriting of the boguscoin addresses, along with the inputs the
+//! `mitm_rewrite` fuzz target found mishandled, kept as tests once fixed.
+
+use protohacker5::{rewrite_message, Settings, BOGUS_REGEX};
+use regex::Regex;
+
+const TONY: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
+
+fn rewrite(message: &str) -> String {
+    rewrite_message(&Regex::new(BOGUS_REGEX).unwrap(), message)
+}
+
+#[test]
+fn rewrites_to_the_configured_address() {
+    let settings = Settings {
+        address: "7OurOwnAddressOurOwnAddress".to_string(),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        settings.rewrite("pay 7F1u3wSD5RbOHQmupo9nx4TnhQ now"),
+        "pay 7OurOwnAddressOurOwnAddress now\n"
+    );
+}
+
+/// mitm_rewrite: `\w` let underscores into addresses.
+#[test]
+fn mitm_leaves_underscores_alone() {
+    let message = "78I8_I__7_____A__I__7_____AA";
+    assert_eq!(rewrite(message), format!("{message}\n"));
+}
+
+/// mitm_rewrite: `\w` let non-ASCII letters into addresses.
+#[test]
+fn mitm_leaves_non_ascii_letters_alone() {
+    let message = "7éééééééééééééééééééééééééé";
+    assert_eq!(rewrite(message), format!("{message}\n"));
+}
+
+/// mitm_rewrite: every occurrence of an address was replaced, even inside a
+/// longer word.
+#[test]
+fn mitm_only_rewrites_whole_words() {
+    let address = "7F1u3wSD5RbOHQmupo9nx4TnhQ";
+
+    assert_eq!(
+        rewrite(&format!("{address} {address}-1234")),
+        format!("{TONY} {address}-1234\n")
+    );
+    assert_eq!(
+        rewrite(&format!("x{address} {address}")),
+        format!("x{address} {TONY}\n")
+    );
+}
@@ -0,0 +1,93 @@
+//! Load generator for the protohackers servers: many clients at once, each
+//! running a workload, with a summary of throughput, latency and errors.
+
+mod stats;
+mod workload;
+
+use clap::Parser;
+use protocore::cli::parse_duration;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use stats::Stats;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use workload::Workload;
+
+/// How long a client waits before reconnecting after an error.
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// Run many clients against a server and report how it held up.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Server to load
+    #[arg(short, long, env = "PROTOHACKER_TARGET", value_name = "HOST:PORT")]
+    target: String,
+
+    /// Clients running at once
+    #[arg(short, long, default_value_t = 10)]
+    clients: usize,
+
+    /// How long to run for
+    #[arg(short, long, default_value = "10s", value_parser = parse_duration)]
+    duration: Duration,
+
+    /// Seed of the random messages, for runs to be comparable
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    #[command(subcommand)]
+    workload: Workload,
+}
+
+/// Run sessions of the workload, reconnecting after errors, until the deadline.
+async fn client(id: usize, cli: Arc<Cli>, started: Instant, deadline: Instant) -> Stats {
+    let mut stats = Stats::new();
+    let mut rng = StdRng::seed_from_u64(cli.seed.wrapping_add(id as u64));
+
+    while Instant::now() < deadline {
+        let session = cli
+            .workload
+            .session(id, &cli.target, &mut rng, &mut stats, started);
+
+        match tokio::time::timeout_at(deadline, session).await {
+            Ok(Ok(())) | Err(_) => {}
+            Ok(Err(e)) => {
+                stats.record_error(e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+
+    stats
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Arc::new(Cli::parse());
+
+    println!(
+        "Running {} {} client(s) against {} for {:?}",
+        cli.clients,
+        cli.workload.name(),
+        cli.target,
+        cli.duration
+    );
+
+    let started = Instant::now();
+    let deadline = started + cli.duration;
+
+    let mut clients = JoinSet::new();
+    for id in 0..cli.clients {
+        clients.spawn(client(id, Arc::clone(&cli), started, deadline));
+    }
+
+    let mut total = Stats::new();
+    while let Some(stats) = clients.join_next().await {
+        total.merge(stats.expect("Client task panicked"));
+    }
+
+    println!("{}", total.report(started.elapsed()));
+}
@@ -0,0 +1,97 @@
+use hdrhistogram::Histogram;
+use std::fmt;
+use std::time::Duration;
+
+/// Latencies above this, in microseconds, are recorded as this.
+const MAX_LATENCY: u64 = 60 * 60 * 1_000_000;
+
+/// What a client saw during a run.
+pub struct Stats {
+    /// Messages sent, whether or not the protocol replies to them.
+    pub messages: u64,
+    pub errors: u64,
+    /// The first error, to give an idea of what went wrong.
+    pub first_error: Option<String>,
+    /// Time to get a reply, or for a chat message to reach another client, in
+    /// microseconds.
+    latencies: Histogram<u64>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            messages: 0,
+            errors: 0,
+            first_error: None,
+            latencies: Histogram::new_with_bounds(1, MAX_LATENCY, 3).expect("the bounds are valid"),
+        }
+    }
+
+    pub fn record_latency(&mut self, latency: Duration) {
+        self.latencies
+            .saturating_record(latency.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+
+    pub fn record_error(&mut self, error: impl fmt::Display) {
+        self.errors += 1;
+        self.first_error.get_or_insert_with(|| error.to_string());
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        self.messages += other.messages;
+        self.errors += other.errors;
+        if self.first_error.is_none() {
+            self.first_error = other.first_error;
+        }
+        self.latencies
+            .add(other.latencies)
+            .expect("histograms have the same bounds");
+    }
+
+    /// A summary of the run, `elapsed` being how long it lasted.
+    pub fn report(&self, elapsed: Duration) -> Report<'_> {
+        Report {
+            stats: self,
+            elapsed,
+        }
+    }
+}
+
+/// The summary printed at the end of a run.
+pub struct Report<'a> {
+    stats: &'a Stats,
+    elapsed: Duration,
+}
+
+fn micros(us: u64) -> Duration {
+    Duration::from_micros(us)
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Report { stats, elapsed } = self;
+        let throughput = stats.messages as f64 / elapsed.as_secs_f64();
+
+        writeln!(f, "elapsed:    {elapsed:.2?}")?;
+        writeln!(f, "messages:   {} ({throughput:.1}/s)", stats.messages)?;
+
+        match &stats.first_error {
+            Some(error) => writeln!(f, "errors:     {} (first: {error})", stats.errors)?,
+            None => writeln!(f, "errors:     0")?,
+        }
+
+        let latencies = &stats.latencies;
+        if latencies.is_empty() {
+            write!(f, "latency:    no replies")
+        } else {
+            write!(
+                f,
+                "latency:    p50 {:.2?}, p99 {:.2?}, max {:.2?} over {} replies",
+                micros(latencies.value_at_quantile(0.5)),
+                micros(latencies.value_at_quantile(0.99)),
+                micros(latencies.max()),
+                latencies.len()
+            )
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::stats::Stats;
+use clap::Subcommand;
+use protoclient::{ChatClient, ClientError, KvClient, MeansClient, PrimeClient};
+use protocore::cli::parse_duration;
+use protocore::io::ReadError;
+use protohacker3::Event;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// Numbers drawn for "large" prime checks are below this: the largest integer
+/// a JSON number carries exactly.
+const LARGE_NUMBERS: u64 = 1 << 53;
+
+/// Numbers drawn for small prime checks are below this.
+const SMALL_NUMBERS: u64 = 10_000;
+
+/// The traffic each client sends.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Workload {
+    /// Smoke test: send random bytes and wait for them to come back
+    Echo {
+        /// Bytes per message
+        #[arg(long, default_value_t = 64)]
+        size: usize,
+    },
+    /// Prime time: check random numbers
+    Prime {
+        /// Percentage of checks on numbers close to 2^53, which are slow to check
+        #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=100))]
+        large: u8,
+    },
+    /// Means to an end: insert prices, then query random periods, over and over
+    Means {
+        /// Prices inserted by each session, at timestamps 0 to N - 1
+        #[arg(
+            long,
+            default_value_t = 50_000,
+            value_parser = clap::value_parser!(u32).range(1..=i32::MAX as i64)
+        )]
+        inserts: u32,
+
+        /// Queries run by each session, once every price is in
+        #[arg(long, default_value_t = 100)]
+        queries: u32,
+    },
+    /// Budget chat: every client joins the room and sends bursts of messages
+    Chat {
+        /// Messages per burst
+        #[arg(long, default_value_t = 10)]
+        burst: u32,
+
+        /// Time between two bursts of a client
+        #[arg(long, default_value = "100ms", value_parser = parse_duration)]
+        pause: Duration,
+    },
+    /// Unusual database program: set and get random keys
+    Kv {
+        /// Number of distinct keys
+        #[arg(long, default_value_t = 100)]
+        keys: u32,
+
+        /// Percentage of requests that set a key rather than get it
+        #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
+        sets: u8,
+    },
+}
+
+impl Workload {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Workload::Echo { .. } => "echo",
+            Workload::Prime { .. } => "prime",
+            Workload::Means { .. } => "means",
+            Workload::Chat { .. } => "chat",
+            Workload::Kv { .. } => "kv",
+        }
+    }
+
+    /// Run a session against `target`: connect and send messages until an
+    /// error, or until the workload is done with the connection. The session
+    /// is cancelled once the run is over.
+    pub async fn session(
+        &self,
+        client: usize,
+        target: &str,
+        rng: &mut StdRng,
+        stats: &mut Stats,
+        started: Instant,
+    ) -> Result<(), ClientError> {
+        match *self {
+            Workload::Echo { size } => echo(target, size, rng, stats).await,
+            Workload::Prime { large } => prime(target, large, rng, stats).await,
+            Workload::Means { inserts, queries } => {
+                means(target, inserts, queries, rng, stats).await
+            }
+            Workload::Chat { burst, pause } => {
+                chat(client, target, burst, pause, stats, started).await
+            }
+            Workload::Kv { keys, sets } => kv(target, keys, sets, rng, stats).await,
+        }
+    }
+}
+
+async fn echo(
+    target: &str,
+    size: usize,
+    rng: &mut StdRng,
+    stats: &mut Stats,
+) -> Result<(), ClientError> {
+    let mut stream = TcpStream::connect(target).await?;
+    let mut message = vec![0; size];
+    let mut echoed = vec![0; size];
+
+    loop {
+        rng.fill_bytes(&mut message);
+
+        let sent = Instant::now();
+        stream.write_all(&message).await?;
+        stream.read_exact(&mut echoed).await?;
+        stats.record_latency(sent.elapsed());
+        stats.messages += 1;
+
+        if echoed != message {
+            return Err(ClientError::InvalidReply(
+                "the echo differs from the message".to_string(),
+            ));
+        }
+    }
+}
+
+async fn prime(
+    target: &str,
+    large: u8,
+    rng: &mut StdRng,
+    stats: &mut Stats,
+) -> Result<(), ClientError> {
+    let mut client = PrimeClient::connect(target).await?;
+
+    loop {
+        let number = if rng.gen_ratio(large.into(), 100) {
+            rng.gen_range(LARGE_NUMBERS / 2..LARGE_NUMBERS)
+        } else {
+            rng.gen_range(0..SMALL_NUMBERS)
+        };
+
+        let sent = Instant::now();
+        client.is_prime(number as f64).await?;
+        stats.record_latency(sent.elapsed());
+        stats.messages += 1;
+    }
+}
+
+async fn means(
+    target: &str,
+    inserts: u32,
+    queries: u32,
+    rng: &mut StdRng,
+    stats: &mut Stats,
+) -> Result<(), ClientError> {
+    // Prices live as long as the connection, so every session starts over
+    let mut client = MeansClient::connect(target).await?;
+    // Timestamps are i32 on the wire, the flag is kept within them
+    let inserts = inserts as i32;
+
+    for timestamp in 0..inserts {
+        client.insert(timestamp, rng.gen_range(0..10_000)).await?;
+        stats.messages += 1;
+    }
+
+    for _ in 0..queries {
+        let min_time = rng.gen_range(0..inserts);
+        let max_time = rng.gen_range(min_time..=inserts);
+
+        let sent = Instant::now();
+        client.query(min_time, max_time).await?;
+        stats.record_latency(sent.elapsed());
+        stats.messages += 1;
+    }
+
+    Ok(())
+}
+
+/// Chat messages carry the time they were sent, since the start of the run,
+/// so whoever receives them can tell how long they took to get there.
+async fn chat(
+    client: usize,
+    target: &str,
+    burst: u32,
+    pause: Duration,
+    stats: &mut Stats,
+    started: Instant,
+) -> Result<(), ClientError> {
+    let mut chat = ChatClient::join(target, &format!("load{client}")).await?;
+    let mut next_burst = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_burst) => {
+                for _ in 0..burst {
+                    let sent = started.elapsed().as_micros();
+                    chat.send(&format!("sent at {sent}")).await?;
+                    stats.messages += 1;
+                }
+                next_burst += pause;
+            }
+            event = chat.next_event() => match event? {
+                Some(Event::Sent(_, message)) => {
+                    let sent = message
+                        .strip_prefix("sent at ")
+                        .and_then(|sent| sent.parse().ok())
+                        .ok_or(ClientError::InvalidReply(message))?;
+                    let received = started.elapsed();
+                    stats.record_latency(received.saturating_sub(Duration::from_micros(sent)));
+                }
+                Some(_) => {}
+                None => return Err(ClientError::Read(ReadError::Eof)),
+            },
+        }
+    }
+}
+
+async fn kv(
+    target: &str,
+    keys: u32,
+    sets: u8,
+    rng: &mut StdRng,
+    stats: &mut Stats,
+) -> Result<(), ClientError> {
+    let client = KvClient::connect(target).await?;
+
+    loop {
+        let key = format!("key{}", rng.gen_range(0..keys.max(1)));
+
+        if rng.gen_ratio(sets.into(), 100) {
+            client.set(&key, &rng.next_u64().to_string()).await?;
+        } else {
+            let sent = Instant::now();
+            client.get(&key).await?;
+            stats.record_latency(sent.elapsed());
+        }
+        stats.messages += 1;
+    }
+}